@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use crate::lsp::formatter::format_document;
+use crate::lsp::workspace::find_mu_files;
+
+const FMT_USAGE: &str = "用法: mutica-lsp fmt [--check] [文件或目录...]";
+
+/// `mutica-lsp fmt [--check] [路径...]`：格式化 `.mu` 文件，返回进程退出码
+///
+/// 目录会递归查找其中的 `.mu` 文件，未指定路径时处理当前目录。
+/// `--check` 只检查不写回，有文件需要格式化时返回 1，便于在 CI 中使用。
+pub fn fmt_command(args: &[String]) -> i32 {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{FMT_USAGE}");
+                return 0;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("未知选项 {arg}\n{FMT_USAGE}");
+                return 2;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    let files = paths.into_iter().flat_map(|path| {
+        if path.is_dir() {
+            find_mu_files(&[path])
+        } else {
+            vec![path]
+        }
+    });
+
+    let mut failed = false;
+    let mut unformatted = 0;
+    for file in files {
+        let content = match std::fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("无法读取 {}: {err}", file.display());
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match format_document(&content, "    ") {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("跳过 {}: {err}", file.display());
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == content {
+            continue;
+        }
+
+        unformatted += 1;
+        if check {
+            println!("需要格式化: {}", file.display());
+        } else if let Err(err) = std::fs::write(&file, formatted) {
+            eprintln!("无法写入 {}: {err}", file.display());
+            failed = true;
+        }
+    }
+
+    if failed || (check && unformatted > 0) {
+        1
+    } else {
+        0
+    }
+}
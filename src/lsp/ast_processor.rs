@@ -1,4 +1,9 @@
-use mutica::mutica_compiler::parser::ParseError;
+use mutica::mutica_compiler::parser::{
+    ParseError, WithLocation,
+    ast::{FlowedMetaData, LinearTypeAst},
+};
+
+type FlowedNode = WithLocation<LinearTypeAst, FlowedMetaData>;
 
 /// 将 ParseError 转换为友好的单行消息
 pub fn perr_to_message(err: &ParseError) -> Option<String> {
@@ -25,3 +30,63 @@ pub fn perr_to_message(err: &ParseError) -> Option<String> {
         ParseError::AstNotDesugared(_) => Some("AST not desugared".to_string()),
     }
 }
+
+/// 节点的直接子节点
+pub fn child_nodes(node: &FlowedNode) -> Vec<&FlowedNode> {
+    use std::iter::once;
+
+    match node.value() {
+        LinearTypeAst::AllOf(items) | LinearTypeAst::AnyOf(items) => items.iter().collect(),
+        LinearTypeAst::Tuple(items) => items.iter().map(|(item, _)| item).collect(),
+        LinearTypeAst::Cons { head, tail } | LinearTypeAst::List { head, tail } => head
+            .iter()
+            .map(|(item, _)| item)
+            .chain(once(tail.as_ref()))
+            .collect(),
+        LinearTypeAst::Match {
+            branches,
+            fail_branch,
+        } => branches
+            .iter()
+            .flat_map(|(pattern, constraints, expr)| {
+                once(pattern)
+                    .chain(constraints.iter().map(|(_, c)| c))
+                    .chain(once(expr))
+            })
+            .chain(fail_branch.as_deref())
+            .collect(),
+        LinearTypeAst::Generic {
+            expr, constraint, ..
+        } => once(expr.as_ref())
+            .chain(constraint.iter().map(|(_, c)| c))
+            .collect(),
+        LinearTypeAst::Invoke {
+            func,
+            arg,
+            continuation,
+            perform_handler,
+        } => [
+            Some(func.as_ref()),
+            Some(arg.as_ref()),
+            continuation.as_deref(),
+            perform_handler.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        LinearTypeAst::Namespace { expr, .. }
+        | LinearTypeAst::Bind { expr, .. }
+        | LinearTypeAst::StaticFixPoint { expr, .. } => vec![expr],
+        LinearTypeAst::Lazy(inner) => vec![inner],
+        LinearTypeAst::Range { ty, .. } => vec![ty],
+        LinearTypeAst::SubOf { value } | LinearTypeAst::Mutable { value } => vec![value],
+        LinearTypeAst::Char
+        | LinearTypeAst::Float
+        | LinearTypeAst::NaturalNumberSet
+        | LinearTypeAst::FloatLiteral(_)
+        | LinearTypeAst::CharLiteral(_)
+        | LinearTypeAst::NaturalNumberLiteral(_)
+        | LinearTypeAst::Variable(_)
+        | LinearTypeAst::AtomicOpcode(_) => Vec::new(),
+    }
+}
@@ -1,34 +1,461 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::sync::RwLock;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
-use crate::lsp::semantic::parse_and_generate_tokens;
-use crate::lsp::utils::{position_in_range, ranges_equal};
+use crate::lsp::code_actions::{auto_import_actions, unused_variable_actions};
+use crate::lsp::completion::{
+    completion_context, import_label_completions, import_path_completions,
+};
+use crate::lsp::document::{Document, Overlay, buffer_overlay, module_source};
+use crate::lsp::folding::{SyntaxSpans, folding_ranges};
+use crate::lsp::formatter::{format_document, format_on_type, format_range, indent_unit};
+use crate::lsp::highlight::document_highlights;
+use crate::lsp::hover::find_hover;
+use crate::lsp::imports::{resolve_import, resolved_imports};
+use crate::lsp::intrinsics;
+use crate::lsp::modifiers;
+use crate::lsp::module_cache::ModuleCache;
+use crate::lsp::scheduler::{AnalysisScheduler, DEBOUNCE_DELAY};
+use crate::lsp::selection::selection_range;
+use crate::lsp::semantic::{
+    parse_and_generate_tokens, semantic_token_edits, semantic_tokens_in_range,
+};
+use crate::lsp::signature::{
+    Signature, find_call_site, find_local_definition, lexical_signature, signature_help,
+};
+use crate::lsp::symbols::{export_chain_start, scan_symbols};
+use crate::lsp::utils::{offset_to_position, position_in_range, position_to_offset, ranges_equal};
+use crate::lsp::workspace::WorkspaceIndex;
 
+/// 语言服务器，处理函数通过 Deref 访问共享状态
+///
+/// 编辑后的防抖分析在后台任务中运行，任务持有 `state` 的一份克隆。
 #[derive(Debug)]
 pub struct Backend {
+    state: Arc<BackendState>,
+}
+
+impl Backend {
+    pub fn new(state: BackendState) -> Self {
+        Self {
+            state: Arc::new(state),
+        }
+    }
+}
+
+impl Deref for Backend {
+    type Target = BackendState;
+
+    fn deref(&self) -> &BackendState {
+        &self.state
+    }
+}
+
+#[derive(Debug)]
+pub struct BackendState {
     pub client: Client,
-    pub documents: RwLock<HashMap<Url, String>>,
+    pub documents: RwLock<HashMap<Url, Document>>,
     pub last_tokens: RwLock<HashMap<Url, SemanticTokens>>,
+    /// 最近一次发送给客户端的语义 token，用于计算增量
+    pub sent_tokens: RwLock<HashMap<Url, SemanticTokens>>,
+    pub next_result_id: AtomicU64,
     pub reference_table: RwLock<HashMap<Url, Vec<(Range, Location)>>>,
     pub variable_maps: RwLock<HashMap<Url, Vec<Option<Vec<String>>>>>,
+    pub hover_tables: RwLock<HashMap<Url, Vec<(Range, String)>>>,
+    pub workspace_folders: RwLock<Vec<PathBuf>>,
+    pub workspace_index: RwLock<WorkspaceIndex>,
+    pub analysis_versions: RwLock<HashMap<Url, i32>>,
+    pub scheduler: AnalysisScheduler,
+    pub module_cache: Arc<Mutex<ModuleCache>>,
+    /// 未打开文件的诊断：文件 URI -> (导入它的文档 -> 该文档分析时得到的诊断)
+    pub imported_diagnostics: RwLock<HashMap<Url, HashMap<Url, Vec<Diagnostic>>>>,
+    pub diagnostics: RwLock<HashMap<Url, Vec<Diagnostic>>>,
+    pub match_spans: RwLock<HashMap<Url, Vec<std::ops::Range<usize>>>>,
+    pub node_spans: RwLock<HashMap<Url, Vec<std::ops::Range<usize>>>>,
+    pub document_symbols: RwLock<HashMap<Url, Vec<DocumentSymbol>>>,
+    pub signatures: RwLock<HashMap<Url, Vec<(Range, Signature)>>>,
+    pub snippet_support: RwLock<bool>,
+}
+
+impl BackendState {
+    /// 获取文档内容：优先使用编辑器中打开的版本，否则从磁盘读取
+    fn document_content(&self, uri: &Url) -> Option<String> {
+        if let Some(document) = self.documents.read().unwrap().get(uri) {
+            return Some(document.text());
+        }
+        std::fs::read_to_string(uri.to_file_path().ok()?).ok()
+    }
+
+    /// 所有打开文档当前内容的快照
+    fn document_snapshot(&self) -> HashMap<Url, String> {
+        self.documents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(uri, document)| (uri.clone(), document.text()))
+            .collect()
+    }
+
+    /// 用编辑器中的内容更新工作区索引中的这个文件
+    fn index_document(&self, uri: &Url, content: &str) {
+        if let Ok(path) = uri.to_file_path() {
+            self.workspace_index.write().unwrap().update(&path, content);
+        }
+    }
+
+    /// 光标所在的 `import "..."` 表达式指向的模块，定位到该模块的导出链
+    fn import_definition(&self, uri: &Url, position: Position) -> Option<Location> {
+        let content = self.document_content(uri)?;
+        let offset = position_to_offset(&content, position)?;
+        let file_path = uri.to_file_path().ok()?;
+        let search_paths = self.workspace_folders.read().unwrap().clone();
+        let (_, target) = resolved_imports(&content, &file_path, &search_paths)
+            .into_iter()
+            .find(|(import, _)| import.span.start <= offset && offset <= import.span.end)?;
+
+        let target_uri = Url::from_file_path(&target).ok()?;
+        // 目标模块未在编辑器中打开时从磁盘读取
+        let target_content =
+            module_source(&self.documents.read().unwrap(), &target).unwrap_or_default();
+        let position = export_chain_start(&target_content)
+            .map(|start| offset_to_position(&target_content, start))
+            .unwrap_or_default();
+        Some(Location {
+            uri: target_uri,
+            range: Range::new(position, position),
+        })
+    }
+
+    /// 沿导入直接或间接依赖 `uri` 的其他打开文档
+    fn open_importers(&self, uri: &Url) -> Vec<Url> {
+        let documents = self.documents.read().unwrap();
+        let Some(changed) = documents.get(uri).and_then(Document::path) else {
+            return Vec::new();
+        };
+        let search_paths = self.workspace_folders.read().unwrap().clone();
+        let cache = self.module_cache.lock().unwrap();
+        documents
+            .iter()
+            .filter(|(other, _)| *other != uri)
+            .filter(|(_, document)| {
+                document.path().is_some_and(|root| {
+                    buffer_overlay(&documents, root, &search_paths, |path| {
+                        cache.imports_of(path)
+                    })
+                    .modules
+                    .contains(changed)
+                })
+            })
+            .map(|(other, _)| other.clone())
+            .collect()
+    }
+
+    /// 打开的文档的当前版本
+    fn document_version(&self, uri: &Url) -> Option<i32> {
+        self.documents
+            .read()
+            .unwrap()
+            .get(uri)
+            .map(Document::version)
+    }
+
+    /// 最近一次分析是否针对文档的当前版本
+    fn analysis_is_current(&self, uri: &Url) -> bool {
+        let version = self.document_version(uri);
+        version.is_some() && self.analysis_versions.read().unwrap().get(uri).copied() == version
+    }
+
+    /// 等待文档当前版本的分析结果，返回结果是否可用
+    ///
+    /// 同一版本的分析（例如编辑后的防抖分析）已在进行时等待它完成，否则在调度器中登记并立即分析。
+    /// 等待期间文档被修改或关闭时放弃，由客户端为新版本重新请求。
+    async fn current_analysis(&self, uri: &Url) -> bool {
+        let Some(version) = self.document_version(uri) else {
+            return false;
+        };
+        let mut analysed = false;
+        loop {
+            let finished = self.scheduler.finished();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+
+            if self.analysis_is_current(uri) {
+                return true;
+            }
+            if self.document_version(uri) != Some(version) {
+                return false;
+            }
+            if !self.scheduler.is_running(uri, version) {
+                // 立即分析后仍不是当前版本说明分析失败，不再重试
+                if analysed {
+                    return false;
+                }
+                analysed = true;
+                self.run_analysis(uri, false).await;
+                continue;
+            }
+            finished.await;
+        }
+    }
+
+    /// 移除文档的所有分析结果，重新打开时不会在首次分析完成前沿用旧的结果
+    fn forget_analysis(&self, uri: &Url) {
+        self.analysis_versions.write().unwrap().remove(uri);
+        self.diagnostics.write().unwrap().remove(uri);
+        self.last_tokens.write().unwrap().remove(uri);
+        self.sent_tokens.write().unwrap().remove(uri);
+        self.reference_table.write().unwrap().remove(uri);
+        self.variable_maps.write().unwrap().remove(uri);
+        self.hover_tables.write().unwrap().remove(uri);
+        self.match_spans.write().unwrap().remove(uri);
+        self.node_spans.write().unwrap().remove(uri);
+        self.document_symbols.write().unwrap().remove(uri);
+        self.signatures.write().unwrap().remove(uri);
+    }
+
+    /// 在后台分析文档的当前版本，并在结果仍然是最新时发布诊断和缓存结果
+    ///
+    /// `debounce` 为 true 时先等待 `DEBOUNCE_DELAY`，期间的新编辑会取消本次分析。
+    /// 返回是否缓存了新的语义 tokens。
+    async fn run_analysis(&self, uri: &Url, debounce: bool) -> bool {
+        let (version, content) = {
+            let documents = self.documents.read().unwrap();
+            let Some(document) = documents.get(uri) else {
+                return false;
+            };
+            (document.version(), document.text())
+        };
+        let Some(cancelled) = self.scheduler.begin(uri, version) else {
+            return false;
+        };
+
+        if debounce {
+            tokio::time::sleep(DEBOUNCE_DELAY).await;
+        }
+        let analysis = if cancelled.load(Ordering::Relaxed) {
+            None
+        } else {
+            // 导入先相对于文档所在目录解析，找不到时再依次查找各工作区目录
+            let search_paths = self.workspace_folders.read().unwrap().clone();
+            // 覆盖层只包含这次分析会导入的打开文档
+            let overlay = {
+                let documents = self.documents.read().unwrap();
+                match documents.get(uri).and_then(Document::path) {
+                    Some(root) => {
+                        let cache = self.module_cache.lock().unwrap();
+                        buffer_overlay(&documents, root, &search_paths, |path| {
+                            cache.imports_of(path)
+                        })
+                    }
+                    None => Overlay::default(),
+                }
+            };
+            let task_uri = uri.clone();
+            let task_cancelled = cancelled.clone();
+            let module_cache = self.module_cache.clone();
+            tokio::task::spawn_blocking(move || {
+                parse_and_generate_tokens(
+                    &content,
+                    &task_uri,
+                    version,
+                    &search_paths,
+                    overlay,
+                    &module_cache,
+                    &task_cancelled,
+                )
+            })
+            .await
+            .ok()
+            .flatten()
+        };
+
+        let Some(analysis) = analysis else {
+            self.scheduler.finish(uri, &cancelled);
+            return false;
+        };
+
+        // 文档在分析期间被修改或关闭时丢弃结果
+        {
+            let documents = self.documents.read().unwrap();
+            if documents.get(uri).map(Document::version) != Some(analysis.version) {
+                drop(documents);
+                self.scheduler.finish(uri, &cancelled);
+                return false;
+            }
+            self.analysis_versions
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.version);
+        }
+
+        let updated = if let Some(mut tokens) = analysis.tokens {
+            let result_id = self.next_result_id.fetch_add(1, Ordering::Relaxed);
+            tokens.result_id = Some(result_id.to_string());
+            self.last_tokens
+                .write()
+                .unwrap()
+                .insert(uri.clone(), tokens);
+            self.reference_table
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.reference_table);
+            if let Some(variable_map) = analysis.variable_map {
+                self.variable_maps
+                    .write()
+                    .unwrap()
+                    .insert(uri.clone(), variable_map);
+            }
+            self.hover_tables
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.hover_table);
+            self.match_spans
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.match_spans);
+            self.node_spans
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.node_spans);
+            self.document_symbols
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.document_symbols);
+            self.signatures
+                .write()
+                .unwrap()
+                .insert(uri.clone(), analysis.signatures);
+            true
+        } else {
+            // 解析失败时不能沿用按旧内容计算的结果：折叠范围、选区和大纲改用词法扫描，
+            // 引用、悬停、变量和签名表移除后相应请求退回或不返回结果
+            self.match_spans.write().unwrap().remove(uri);
+            self.node_spans.write().unwrap().remove(uri);
+            self.document_symbols.write().unwrap().remove(uri);
+            self.reference_table.write().unwrap().remove(uri);
+            self.hover_tables.write().unwrap().remove(uri);
+            self.variable_maps.write().unwrap().remove(uri);
+            self.signatures.write().unwrap().remove(uri);
+            false
+        };
+        // 结果全部写入后才结束登记，等待这次分析的请求醒来时能读到完整的结果
+        self.scheduler.finish(uri, &cancelled);
+
+        self.diagnostics
+            .write()
+            .unwrap()
+            .insert(uri.clone(), analysis.diagnostics.clone());
+        self.client
+            .publish_diagnostics(uri.clone(), analysis.diagnostics, Some(analysis.version))
+            .await;
+        self.publish_imported_diagnostics(uri, analysis.imported_diagnostics)
+            .await;
+        updated
+    }
+
+    /// 发布 `uri` 的分析在导入文件中发现的诊断
+    ///
+    /// 同一文件可能被多个打开的文档导入，每个文件发布所有导入者得到的诊断的并集；
+    /// 在编辑器中打开的文件由它自己的分析发布诊断。
+    async fn publish_imported_diagnostics(
+        &self,
+        uri: &Url,
+        imported_diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    ) {
+        let updates: Vec<(Url, Vec<Diagnostic>)> = {
+            let documents = self.documents.read().unwrap();
+            let mut by_file = self.imported_diagnostics.write().unwrap();
+
+            let mut affected: HashSet<Url> = by_file
+                .iter_mut()
+                .filter_map(|(file_uri, importers)| importers.remove(uri).map(|_| file_uri.clone()))
+                .collect();
+            for (file_uri, diagnostics) in imported_diagnostics {
+                if file_uri == *uri || documents.contains_key(&file_uri) {
+                    continue;
+                }
+                by_file
+                    .entry(file_uri.clone())
+                    .or_default()
+                    .insert(uri.clone(), diagnostics);
+                affected.insert(file_uri);
+            }
+
+            let mut updates = Vec::new();
+            for file_uri in affected {
+                if documents.contains_key(&file_uri) {
+                    continue;
+                }
+                let mut union: Vec<Diagnostic> = Vec::new();
+                for diagnostic in by_file
+                    .get(&file_uri)
+                    .into_iter()
+                    .flat_map(|i| i.values().flatten())
+                {
+                    if !union.contains(diagnostic) {
+                        union.push(diagnostic.clone());
+                    }
+                }
+                updates.push((file_uri, union));
+            }
+            by_file.retain(|_, importers| !importers.is_empty());
+            updates
+        };
+
+        for (file_uri, diagnostics) in updates {
+            self.client
+                .publish_diagnostics(file_uri, diagnostics, None)
+                .await;
+        }
+    }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        // 记录工作区目录，未提供 workspace_folders 时退回到 root_uri
+        let folders: Vec<PathBuf> = match params.workspace_folders {
+            Some(folders) => folders
+                .iter()
+                .filter_map(|f| f.uri.to_file_path().ok())
+                .collect(),
+            None => params
+                .root_uri
+                .and_then(|uri| uri.to_file_path().ok())
+                .into_iter()
+                .collect(),
+        };
+        *self.workspace_folders.write().unwrap() = folders;
+
+        // 客户端声明支持 snippet 时才发送代码片段补全
+        *self.snippet_support.write().unwrap() = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|item| item.snippet_support)
+            .unwrap_or(false);
+
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec![".".to_string()]),
+                    resolve_provider: Some(true),
+                    trigger_characters: Some(vec![
+                        ".".to_string(),
+                        "\"".to_string(),
+                        "/".to_string(),
+                    ]),
                     work_done_progress_options: Default::default(),
                     all_commit_characters: None,
                     completion_item: None,
@@ -44,9 +471,37 @@ impl LanguageServer for Backend {
                     }),
                     file_operations: None,
                 }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: Some(vec![")".to_string()]),
+                    work_done_progress_options: Default::default(),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                        work_done_progress_options: Default::default(),
+                        resolve_provider: None,
+                    },
+                )),
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
                 rename_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".to_string(),
+                    more_trigger_character: Some(vec![";".to_string(), "|".to_string()]),
+                }),
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensOptions(
                         SemanticTokensOptions {
@@ -76,21 +531,10 @@ impl LanguageServer for Backend {
                                     SemanticTokenType::REGEXP,
                                     SemanticTokenType::OPERATOR,
                                 ],
-                                token_modifiers: vec![
-                                    SemanticTokenModifier::DECLARATION,
-                                    SemanticTokenModifier::DEFINITION,
-                                    SemanticTokenModifier::READONLY,
-                                    SemanticTokenModifier::STATIC,
-                                    SemanticTokenModifier::DEPRECATED,
-                                    SemanticTokenModifier::ABSTRACT,
-                                    SemanticTokenModifier::ASYNC,
-                                    SemanticTokenModifier::MODIFICATION,
-                                    SemanticTokenModifier::DOCUMENTATION,
-                                    SemanticTokenModifier::DEFAULT_LIBRARY,
-                                ],
+                                token_modifiers: modifiers::LEGEND.to_vec(),
                             },
-                            range: Some(false),
-                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            range: Some(true),
+                            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                         },
                     ),
                 ),
@@ -100,6 +544,37 @@ impl LanguageServer for Backend {
     }
 
     async fn initialized(&self, _: InitializedParams) {
+        // 扫描工作区建立符号索引，已打开的文档以编辑器中的内容为准
+        let folders = self.workspace_folders.read().unwrap().clone();
+        let index = tokio::task::spawn_blocking(move || WorkspaceIndex::build(&folders))
+            .await
+            .unwrap_or_default();
+        self.workspace_index.write().unwrap().merge(index);
+        for (uri, content) in self.document_snapshot() {
+            self.index_document(&uri, &content);
+        }
+
+        // 监视磁盘上 `.mu` 文件的变化，保持索引与未打开的文件同步
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.mu".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "mutica-watched-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(err) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to watch workspace files: {}", err),
+                )
+                .await;
+        }
+
         self.client
             .log_message(MessageType::INFO, "Mutica LSP server initialized!")
             .await;
@@ -110,10 +585,16 @@ impl LanguageServer for Backend {
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        self.index_document(&params.text_document.uri, &params.text_document.text);
+        let document = Document::new(
+            &params.text_document.uri,
+            &params.text_document.text,
+            params.text_document.version,
+        );
         self.documents
             .write()
             .unwrap()
-            .insert(params.text_document.uri, params.text_document.text);
+            .insert(params.text_document.uri, document);
         self.client
             .log_message(MessageType::INFO, "file opened!")
             .await;
@@ -121,66 +602,179 @@ impl LanguageServer for Backend {
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri.clone();
-        // chdir to uri's parent directory
-        std::env::set_current_dir(
-            uri.to_file_path()
-                .unwrap()
-                .parent()
-                .unwrap_or_else(|| std::path::Path::new(".")),
-        )
-        .unwrap_or(());
-        if let Some(change) = params.content_changes.first() {
-            self.documents
-                .write()
-                .unwrap()
-                .insert(uri.clone(), change.text.clone());
+        // 按顺序应用增量修改
+        let content = self
+            .documents
+            .write()
+            .unwrap()
+            .get_mut(&uri)
+            .map(|document| {
+                document.apply_changes(params.content_changes, params.text_document.version);
+                document.text()
+            });
+        if let Some(content) = content {
+            self.index_document(&uri, &content);
+        }
+        // 直接或间接导入了这个文件的其他打开文档在下次请求时基于新内容重新分析
+        let importers = self.open_importers(&uri);
+        let mut analysis_versions = self.analysis_versions.write().unwrap();
+        for importer in importers {
+            analysis_versions.remove(&importer);
+        }
+        drop(analysis_versions);
 
-            // 先尝试解析，只有成功时才触发 semantic_tokens_refresh
-            let result = parse_and_generate_tokens(&change.text, &uri, &self.client).await;
-            if let Ok((Some(tokens), reference_table, variable_map_opt)) = result {
-                // 解析成功，缓存 tokens 和引用表
-                self.last_tokens
-                    .write()
-                    .unwrap()
-                    .insert(uri.clone(), tokens);
-                self.reference_table
-                    .write()
-                    .unwrap()
-                    .insert(uri.clone(), reference_table);
-                if let Some(variable_map) = variable_map_opt {
-                    self.variable_maps
-                        .write()
-                        .unwrap()
-                        .insert(uri.clone(), variable_map);
-                }
-                let _ = self.client.semantic_tokens_refresh().await;
+        // 防抖和分析在调度器持有的后台任务中进行，did_change 立即返回；
+        // 只有得到新的 tokens 时才触发 semantic_tokens_refresh
+        let state = self.state.clone();
+        let task_uri = uri.clone();
+        self.scheduler.spawn(&uri, async move {
+            if state.run_analysis(&task_uri, true).await {
+                let _ = state.client.semantic_tokens_refresh().await;
             }
-        }
+        });
 
         self.client
             .log_message(MessageType::INFO, "file changed!")
             .await;
     }
 
-    async fn did_save(&self, _: DidSaveTextDocumentParams) {
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let mut added = Vec::new();
+        {
+            let mut folders = self.workspace_folders.write().unwrap();
+            let mut index = self.workspace_index.write().unwrap();
+            for removed in params.event.removed {
+                if let Ok(path) = removed.uri.to_file_path() {
+                    folders.retain(|f| *f != path);
+                    index.remove_folder(&path);
+                }
+            }
+            for folder in params.event.added {
+                if let Ok(path) = folder.uri.to_file_path()
+                    && !folders.contains(&path)
+                {
+                    folders.push(path.clone());
+                    added.push(path);
+                }
+            }
+        }
+
+        // 新目录在后台扫描后并入索引，已打开的文档仍以编辑器中的内容为准
+        let index = tokio::task::spawn_blocking(move || WorkspaceIndex::build(&added))
+            .await
+            .unwrap_or_default();
+        self.workspace_index.write().unwrap().merge(index);
+        for (uri, content) in self.document_snapshot() {
+            self.index_document(&uri, &content);
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let documents = self.documents.read().unwrap();
+        let mut index = self.workspace_index.write().unwrap();
+        let mut module_cache = self.module_cache.lock().unwrap();
+        for change in params.changes {
+            // 磁盘上的内容变化后，缓存中按磁盘内容解析的模块及导入它们的模块都要重新解析
+            if let Ok(path) = change.uri.to_file_path() {
+                module_cache.invalidate_path(&path.canonicalize().unwrap_or(path));
+            }
+            // 打开的文档由 did_change 维护
+            if documents.contains_key(&change.uri) {
+                continue;
+            }
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            if change.typ == FileChangeType::DELETED {
+                index.remove(&path);
+            } else {
+                index.reload(&path);
+            }
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let content = params
+            .text
+            .or_else(|| self.documents.read().unwrap().get(&uri).map(Document::text));
+        if let Some(content) = content {
+            self.index_document(&uri, &content);
+        }
         self.client
             .log_message(MessageType::INFO, "file saved!")
             .await;
     }
 
-    async fn did_close(&self, _: DidCloseTextDocumentParams) {
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.scheduler.cancel(&uri);
+        self.documents.write().unwrap().remove(&uri);
+        self.forget_analysis(&uri);
+        // 撤回文档自身的诊断以及它的分析在导入文件中报告的诊断
+        self.client
+            .publish_diagnostics(uri.clone(), Vec::new(), None)
+            .await;
+        self.publish_imported_diagnostics(&uri, HashMap::new())
+            .await;
+
+        // 关闭后以磁盘内容为准，工作区以外的文件不再索引
+        if let Ok(path) = uri.to_file_path() {
+            let in_workspace = self
+                .workspace_folders
+                .read()
+                .unwrap()
+                .iter()
+                .any(|folder| path.starts_with(folder));
+            let mut index = self.workspace_index.write().unwrap();
+            if in_workspace {
+                index.reload(&path);
+            } else {
+                index.remove(&path);
+            }
+        }
         self.client
             .log_message(MessageType::INFO, "file closed!")
             .await;
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        let mut items = crate::lsp::completion::get_completion_items();
-
         // 获取位置信息
         let uri = params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
 
+        let Some(content) = self.document_content(&uri) else {
+            return Ok(None);
+        };
+
+        if let Ok(file_path) = uri.to_file_path() {
+            let search_paths = self.workspace_folders.read().unwrap().clone();
+
+            // 导入路径字符串中只补全模块路径
+            if let Some(items) =
+                import_path_completions(&content, position, &file_path, &search_paths)
+            {
+                return Ok(Some(CompletionResponse::Array(items)));
+            }
+
+            // 导入解构模式中只补全被导入模块导出的标签
+            let base_dir = file_path.parent().unwrap_or(Path::new("."));
+            let read_module = |spec: &str| {
+                let target = resolve_import(spec, base_dir, &search_paths)?;
+                module_source(&self.documents.read().unwrap(), &target)
+            };
+            if let Some(items) = import_label_completions(&content, position, read_module) {
+                return Ok(Some(CompletionResponse::Array(items)));
+            }
+        }
+
+        // 注释和字符串中不提供补全
+        let Some(context) = completion_context(&content, position) else {
+            return Ok(None);
+        };
+        let snippet_support = *self.snippet_support.read().unwrap();
+        let mut items = crate::lsp::completion::get_completion_items(context, snippet_support);
+
         // 从变量映射获取变量补全
         if let Some(variable_items) = crate::lsp::completion::get_variable_completions(
             &uri,
@@ -194,6 +788,19 @@ impl LanguageServer for Backend {
         Ok(Some(CompletionResponse::Array(items)))
     }
 
+    async fn completion_resolve(&self, mut item: CompletionItem) -> Result<CompletionItem> {
+        if item.kind == Some(CompletionItemKind::FUNCTION)
+            && let Some(intrinsic) = intrinsics::lookup(&item.label)
+        {
+            item.detail = Some(intrinsic.signature());
+            item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: intrinsic.documentation(),
+            }));
+        }
+        Ok(item)
+    }
+
     async fn execute_command(&self, _: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
         self.client
             .log_message(MessageType::INFO, "command executed!")
@@ -213,42 +820,70 @@ impl LanguageServer for Backend {
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
         let uri = params.text_document.uri;
-        std::env::set_current_dir(
-            uri.to_file_path()
+
+        // 缓存已是当前版本时直接返回；否则立即分析（同一版本的分析已在进行时返回旧缓存，
+        // 那次分析完成后会触发 semantic_tokens_refresh）
+        if !self.analysis_is_current(&uri) {
+            self.run_analysis(&uri, false).await;
+        }
+        let tokens = self.last_tokens.read().unwrap().get(&uri).cloned();
+        if let Some(tokens) = &tokens {
+            self.sent_tokens
+                .write()
                 .unwrap()
-                .parent()
-                .unwrap_or_else(|| std::path::Path::new(".")),
-        )
-        .unwrap_or(());
-        let content = self.documents.read().unwrap().get(&uri).cloned();
-        if let Some(content) = content {
-            let result = parse_and_generate_tokens(&content, &uri, &self.client).await?;
-            if let (Some(tokens), reference_table, variable_map_opt) = result {
-                self.last_tokens
-                    .write()
-                    .unwrap()
-                    .insert(uri.clone(), tokens.clone());
-                self.reference_table
-                    .write()
-                    .unwrap()
-                    .insert(uri.clone(), reference_table);
-                if let Some(variable_map) = variable_map_opt {
-                    self.variable_maps
-                        .write()
-                        .unwrap()
-                        .insert(uri.clone(), variable_map);
-                }
-                Ok(Some(SemanticTokensResult::Tokens(tokens)))
-            } else if let Some(cached) = self.last_tokens.read().unwrap().get(&uri).cloned() {
-                Ok(Some(SemanticTokensResult::Tokens(cached)))
-            } else {
-                Ok(None)
+                .insert(uri, tokens.clone());
+        }
+        Ok(tokens.map(SemanticTokensResult::Tokens))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let uri = params.text_document.uri;
+
+        if !self.analysis_is_current(&uri) {
+            self.run_analysis(&uri, false).await;
+        }
+        let Some(tokens) = self.last_tokens.read().unwrap().get(&uri).cloned() else {
+            return Ok(None);
+        };
+
+        // 客户端持有的结果与上次发送的一致时只发送差异，否则退回完整结果
+        let previous = self
+            .sent_tokens
+            .write()
+            .unwrap()
+            .insert(uri, tokens.clone());
+        match previous {
+            Some(previous)
+                if previous.result_id.as_deref() == Some(params.previous_result_id.as_str()) =>
+            {
+                Ok(Some(SemanticTokensFullDeltaResult::TokensDelta(
+                    SemanticTokensDelta {
+                        edits: semantic_token_edits(&previous.data, &tokens.data),
+                        result_id: tokens.result_id,
+                    },
+                )))
             }
-        } else {
-            Ok(None)
+            _ => Ok(Some(SemanticTokensFullDeltaResult::Tokens(tokens))),
         }
     }
 
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        // 只截取缓存中的结果，不为可见区域重新分析；分析完成后会触发 semantic_tokens_refresh
+        let tokens = self.last_tokens.read().unwrap();
+        Ok(tokens.get(&params.text_document.uri).map(|tokens| {
+            SemanticTokensRangeResult::Tokens(SemanticTokens {
+                result_id: None,
+                data: semantic_tokens_in_range(&tokens.data, params.range),
+            })
+        }))
+    }
+
     async fn goto_definition(
         &self,
         params: GotoDefinitionParams,
@@ -256,15 +891,238 @@ impl LanguageServer for Backend {
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
-        let table = self.reference_table.read().unwrap();
-        if let Some(references) = table.get(&uri) {
-            for (use_range, def_location) in references {
-                if position_in_range(&position, use_range) {
-                    return Ok(Some(GotoDefinitionResponse::Scalar(def_location.clone())));
+        // 引用表只对分析时的版本有效，等待当前版本的分析完成；分析失败时只尝试导入路径
+        if self.current_analysis(&uri).await {
+            let table = self.reference_table.read().unwrap();
+            if let Some(references) = table.get(&uri) {
+                for (use_range, def_location) in references {
+                    if position_in_range(&position, use_range) {
+                        return Ok(Some(GotoDefinitionResponse::Scalar(def_location.clone())));
+                    }
                 }
             }
         }
-        Ok(None)
+
+        Ok(self
+            .import_definition(&uri, position)
+            .map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        // 悬停表按分析时的内容计算，等待当前版本的分析完成；分析失败时只提供内建函数说明
+        let current = self.current_analysis(&uri).await;
+        let tables = self.hover_tables.read().unwrap();
+        let hover = tables
+            .get(&uri)
+            .filter(|_| current)
+            .and_then(|table| find_hover(table, &position))
+            .map(|(range, markdown)| (*range, markdown.clone()))
+            .or_else(|| {
+                // 内建函数没有定义位置，改用内建函数目录中的说明
+                let content = self.document_content(&uri)?;
+                let (intrinsic, range) = intrinsics::intrinsic_at(&content, position)?;
+                Some((range, intrinsic.documentation()))
+            })
+            .map(|(range, markdown)| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: markdown,
+                }),
+                range: Some(range),
+            });
+        Ok(hover)
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let Some(content) = self.documents.read().unwrap().get(&uri).map(Document::text) else {
+            return Ok(None);
+        };
+        let Some(call) = position_to_offset(&content, position)
+            .and_then(|offset| find_call_site(&content, offset))
+        else {
+            return Ok(None);
+        };
+
+        // 被调用者的签名在分析时由语法树沿引用关系登记到使用处，只对分析时的版本有效
+        let callee_position = offset_to_position(&content, call.callee_span.start);
+        let analysed = self
+            .analysis_is_current(&uri)
+            .then(|| {
+                self.signatures
+                    .read()
+                    .unwrap()
+                    .get(&uri)
+                    .and_then(|signatures| {
+                        signatures
+                            .iter()
+                            .find(|(use_range, _)| position_in_range(&callee_position, use_range))
+                            .map(|(_, signature)| signature.clone())
+                    })
+            })
+            .flatten();
+        // 正在输入调用时文档通常无法解析，退回当前文档中的词法定义
+        let signature = analysed.or_else(|| {
+            let def_offset = find_local_definition(&content, &call.callee, call.callee_span.start)?;
+            lexical_signature(&content, def_offset)
+        });
+        Ok(signature.and_then(|signature| signature_help(&call, &signature)))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let Some(content) = self.documents.read().unwrap().get(&uri).map(Document::text) else {
+            return Ok(None);
+        };
+        let file_diagnostics = self
+            .diagnostics
+            .read()
+            .unwrap()
+            .get(&uri)
+            .cloned()
+            .unwrap_or_default();
+
+        let mut actions = unused_variable_actions(
+            &uri,
+            &content,
+            &params.context.diagnostics,
+            &file_diagnostics,
+        );
+
+        // 自动导入：在工作区中查找导出了未声明变量的模块
+        let folders = self.workspace_folders.read().unwrap().clone();
+        let index = self.workspace_index.read().unwrap();
+        actions.extend(auto_import_actions(
+            &uri,
+            &content,
+            &params.context.diagnostics,
+            &folders,
+            |name| index.modules_exporting(name),
+        ));
+        Ok((!actions.is_empty()).then_some(actions))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        if self.current_analysis(&uri).await
+            && let Some(symbols) = self.document_symbols.read().unwrap().get(&uri).cloned()
+        {
+            return Ok(Some(DocumentSymbolResponse::Nested(symbols)));
+        }
+        // 解析失败时没有语法树，退回词法扫描
+        let content = self.documents.read().unwrap().get(&uri).map(Document::text);
+        Ok(content.map(|content| DocumentSymbolResponse::Nested(scan_symbols(&content))))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let Some(content) = self.document_content(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        let Ok(formatted) = format_document(&content, &indent_unit(&params.options)) else {
+            return Ok(None);
+        };
+        if formatted == content {
+            return Ok(Some(Vec::new()));
+        }
+        Ok(Some(vec![TextEdit {
+            range: Range::new(
+                Position::new(0, 0),
+                offset_to_position(&content, content.len()),
+            ),
+            new_text: formatted,
+        }]))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let Some(content) = self.document_content(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(format_range(
+            &content,
+            params.range,
+            &indent_unit(&params.options),
+        )))
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let position = params.text_document_position;
+        let Some(content) = self.document_content(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let edits = format_on_type(
+            &content,
+            position.position,
+            &params.ch,
+            &indent_unit(&params.options),
+        );
+        Ok((!edits.is_empty()).then_some(edits))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = params.text_document.uri;
+        let Some(content) = self.document_content(&uri) else {
+            return Ok(None);
+        };
+        // 语法树只在最近一次分析对应当前版本时可用
+        let spans = if self.analysis_is_current(&uri) {
+            let node_spans = self.node_spans.read().unwrap().get(&uri).cloned();
+            let match_spans = self.match_spans.read().unwrap().get(&uri).cloned();
+            node_spans.zip(match_spans)
+        } else {
+            None
+        };
+        let syntax = spans
+            .as_ref()
+            .map(|(nodes, matches)| SyntaxSpans { nodes, matches });
+        Ok(Some(folding_ranges(&content, syntax)))
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        let uri = params.text_document.uri;
+        let current = self.current_analysis(&uri).await;
+        let Some(content) = self.document_content(&uri) else {
+            return Ok(None);
+        };
+        // 节点范围只对分析时的版本有效
+        let spans = if current {
+            self.node_spans.read().unwrap().get(&uri).cloned()
+        } else {
+            None
+        };
+        let spans = spans.unwrap_or_default();
+        Ok(Some(
+            params
+                .positions
+                .into_iter()
+                .map(|position| selection_range(&content, &spans, position))
+                .collect(),
+        ))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        Ok(Some(
+            self.workspace_index.read().unwrap().symbols(&params.query),
+        ))
     }
 
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
@@ -272,6 +1130,10 @@ impl LanguageServer for Backend {
         let position = params.text_document_position.position;
         let include_declaration = params.context.include_declaration;
 
+        // 引用表按分析时的内容计算，等待当前版本的分析完成；等待期间文档又被修改时不返回结果
+        if !self.current_analysis(&uri).await {
+            return Ok(None);
+        }
         let table = self.reference_table.read().unwrap();
 
         // 首先找到目标定义的位置
@@ -341,11 +1203,61 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        // 引用表按分析时的内容计算，等待当前版本的分析完成；等待期间文档又被修改时不返回结果
+        if !self.current_analysis(&uri).await {
+            return Ok(None);
+        }
+        let Some(content) = self.document_content(&uri) else {
+            return Ok(None);
+        };
+        let table = self.reference_table.read().unwrap();
+        let Some(references) = table.get(&uri) else {
+            return Ok(None);
+        };
+        Ok(document_highlights(&content, &uri, references, position))
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        let (Some(content), Ok(file_path)) = (self.document_content(&uri), uri.to_file_path())
+        else {
+            return Ok(None);
+        };
+        let search_paths = self.workspace_folders.read().unwrap().clone();
+        let links = resolved_imports(&content, &file_path, &search_paths)
+            .into_iter()
+            .map(|(import, target)| {
+                let span = import.path_span();
+                DocumentLink {
+                    range: Range::new(
+                        offset_to_position(&content, span.start),
+                        offset_to_position(&content, span.end),
+                    ),
+                    target: Url::from_file_path(&target).ok(),
+                    tooltip: Some(target.display().to_string()),
+                    data: None,
+                }
+            })
+            .collect();
+        Ok(Some(links))
+    }
+
     async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
         let uri = params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
         let new_name = params.new_name;
 
+        // 引用表按分析时的内容计算，等待当前版本的分析完成；等待期间文档又被修改时不返回结果
+        if !self.current_analysis(&uri).await {
+            return Ok(None);
+        }
         let table = self.reference_table.read().unwrap();
 
         // 首先找到目标定义的位置
@@ -391,11 +1303,11 @@ impl LanguageServer for Backend {
         if let Some(def_location) = target_def_location {
             // URI 规范化：转成文件路径再比较
             let def_path = def_location.uri.to_file_path().ok();
-            
+
             // 直接在 changes 层面去重
             let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
             let mut processed: HashSet<(String, u32, u32, u32, u32)> = HashSet::new();
-            
+
             // URI 规范化映射
             let mut uri_map: HashMap<String, Url> = HashMap::new();
             let mut get_canonical_uri = |uri: &Url| -> Url {
@@ -426,7 +1338,7 @@ impl LanguageServer for Backend {
                         range.end.character,
                     )
                 };
-                
+
                 if processed.insert(key) {
                     let canonical = get_canonical_uri(uri);
                     changes.entry(canonical).or_default().push(TextEdit {
@@ -444,7 +1356,8 @@ impl LanguageServer for Backend {
                 for (use_range, d_location) in references {
                     // 用文件路径比较，不用 URI 字符串
                     let d_path = d_location.uri.to_file_path().ok();
-                    if d_path.is_some() && d_path == def_path
+                    if d_path.is_some()
+                        && d_path == def_path
                         && ranges_equal(&d_location.range, &def_location.range)
                     {
                         add_edit(file_uri, *use_range);
@@ -0,0 +1,594 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Diagnostic, NumberOrString, Range, TextEdit,
+    Url, WorkspaceEdit,
+};
+
+use crate::lsp::imports::{module_aliases, resolve_import};
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::symbols::statement_end;
+use crate::lsp::utils::{offset_to_position, position_to_offset};
+
+/// 未使用变量诊断的 `code`，诊断的 `data` 中携带变量名 `{"name": ...}`
+pub const UNUSED_VARIABLE_CODE: &str = "unused-variable";
+
+/// 未声明变量诊断的 `code`，`data` 格式同上
+pub const UNDECLARED_VARIABLE_CODE: &str = "undeclared-variable";
+
+/// 为光标处的未使用变量诊断生成快速修复：加 `_` 前缀、删除绑定（安全时），
+/// 以及文件中存在多个未使用变量时的“全部加 `_` 前缀”
+pub fn unused_variable_actions(
+    uri: &Url,
+    content: &str,
+    context_diagnostics: &[Diagnostic],
+    file_diagnostics: &[Diagnostic],
+) -> Vec<CodeActionOrCommand> {
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let mut actions = Vec::new();
+
+    let in_context: Vec<(&Diagnostic, usize, String)> = context_diagnostics
+        .iter()
+        .filter_map(|d| locate_unused(content, d).map(|(start, name)| (d, start, name)))
+        .collect();
+
+    for (diagnostic, start, name) in &in_context {
+        actions.push(quick_fix(
+            format!("Prefix `{}` with an underscore", name),
+            uri,
+            vec![prefix_edit(content, *start)],
+            vec![(*diagnostic).clone()],
+            true,
+        ));
+
+        if let Some(range) = removable_binding(content, &tokens, *start) {
+            actions.push(quick_fix(
+                format!("Remove unused binding `{}`", name),
+                uri,
+                vec![TextEdit {
+                    range,
+                    new_text: String::new(),
+                }],
+                vec![(*diagnostic).clone()],
+                false,
+            ));
+        }
+    }
+
+    let all_unused: Vec<(&Diagnostic, usize)> = file_diagnostics
+        .iter()
+        .filter_map(|d| locate_unused(content, d).map(|(start, _)| (d, start)))
+        .collect();
+    if !in_context.is_empty() && all_unused.len() > 1 {
+        actions.push(quick_fix(
+            "Prefix all unused variables in file with an underscore".to_string(),
+            uri,
+            all_unused
+                .iter()
+                .map(|(_, start)| prefix_edit(content, *start))
+                .collect(),
+            all_unused.iter().map(|(d, _)| (*d).clone()).collect(),
+            false,
+        ));
+    }
+
+    actions
+}
+
+/// 为未声明变量生成自动导入：`find_modules` 返回导出链中提供该标签的模块
+///
+/// 如果当前文件已有从同一模块解构的 `let constraint { ... } = ...;` 块，则在块中追加一项，
+/// 否则在最后一条导入语句之后插入 `let constraint X::(X: any) = import "m.mu";`。
+pub fn auto_import_actions(
+    uri: &Url,
+    content: &str,
+    context_diagnostics: &[Diagnostic],
+    search_paths: &[PathBuf],
+    find_modules: impl Fn(&str) -> Vec<PathBuf>,
+) -> Vec<CodeActionOrCommand> {
+    let Ok(file_path) = uri.to_file_path() else {
+        return Vec::new();
+    };
+    let current = file_path
+        .canonicalize()
+        .unwrap_or_else(|_| file_path.clone());
+    let base_dir = current.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+
+    let mut actions = Vec::new();
+    let mut seen = HashSet::new();
+    for diagnostic in context_diagnostics {
+        if diagnostic.code != Some(NumberOrString::String(UNDECLARED_VARIABLE_CODE.to_string())) {
+            continue;
+        }
+        let Some(name) = diagnostic
+            .data
+            .as_ref()
+            .and_then(|data| data.get("name")?.as_str())
+        else {
+            continue;
+        };
+        if !seen.insert(name.to_string()) || name.starts_with('$') {
+            continue;
+        }
+
+        for module in find_modules(name) {
+            let module = module.canonicalize().unwrap_or(module);
+            if module == current {
+                continue;
+            }
+            let spec = relative_import_path(&base_dir, &module);
+            let edit =
+                extend_import_block(content, &tokens, &module, name, &base_dir, search_paths)
+                    .unwrap_or_else(|| insert_import(content, &tokens, name, &spec));
+            actions.push(quick_fix(
+                format!("Import `{}` from \"{}\"", name, spec),
+                uri,
+                vec![edit],
+                vec![diagnostic.clone()],
+                false,
+            ));
+        }
+    }
+    actions
+}
+
+/// 在从 `module` 解构的导入块末尾追加 `name::(name: any)`
+///
+/// 块的右侧可以直接是 `import "..."`，也可以是绑定到 `import "..."` 的顶层变量（如 `list_pkg`）。
+fn extend_import_block(
+    content: &str,
+    tokens: &[Token],
+    module: &Path,
+    name: &str,
+    base_dir: &Path,
+    search_paths: &[PathBuf],
+) -> Option<TextEdit> {
+    let resolves_to_module =
+        |spec: &str| resolve_import(spec, base_dir, search_paths).as_deref() == Some(module);
+    let aliases = module_aliases(tokens);
+
+    for i in 0..tokens.len() {
+        let is_block = matches!(
+            &tokens[i..],
+            [keyword, constraint, open, ..]
+                if keyword.text == "let" && constraint.text == "constraint" && open.is_punct("{")
+        );
+        if !is_block {
+            continue;
+        }
+        let close = statement_end(tokens, i + 3, true);
+        if !tokens.get(close).is_some_and(|t| t.is_punct("}"))
+            || !tokens.get(close + 1).is_some_and(|t| t.is_punct("="))
+        {
+            continue;
+        }
+        let source_matches = match (tokens.get(close + 2), tokens.get(close + 3)) {
+            (Some(keyword), Some(string))
+                if keyword.text == "import" && string.kind == TokenKind::String =>
+            {
+                resolves_to_module(&string.text[1..string.text.len() - 1])
+            }
+            (Some(alias), _) if alias.kind == TokenKind::Ident => aliases
+                .get(alias.text)
+                .is_some_and(|spec| resolves_to_module(spec)),
+            _ => false,
+        };
+        if !source_matches || close == i + 3 {
+            continue;
+        }
+
+        let first_entry = tokens[i + 3].span.start;
+        let line_start = content[..first_entry].rfind('\n').map_or(0, |p| p + 1);
+        let indent = &content[line_start..first_entry];
+        let indent = if indent.trim().is_empty() {
+            indent
+        } else {
+            "    "
+        };
+        let position = offset_to_position(content, tokens[close - 1].span.end);
+        return Some(TextEdit {
+            range: Range {
+                start: position,
+                end: position,
+            },
+            new_text: format!(" &\n{}{}::({}: any)", indent, name, name),
+        });
+    }
+    None
+}
+
+/// 在最后一条含 `import` 的语句所在行之后插入新的导入语句，没有导入时插入到文件开头
+fn insert_import(content: &str, tokens: &[Token], name: &str, spec: &str) -> TextEdit {
+    let statement = format!(
+        "let constraint {}::({}: any) = import \"{}\";",
+        name, name, spec
+    );
+    let last_import = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.text == "import")
+        .map(|(i, _)| statement_end(tokens, i, false))
+        .filter_map(|end| tokens.get(end.checked_sub(1)?))
+        .map(|t| t.span.end)
+        .next_back();
+
+    let (offset, new_text) = match last_import {
+        Some(end) => match content[end..].find('\n') {
+            Some(newline) => (end + newline + 1, format!("{}\n", statement)),
+            None => (content.len(), format!("\n{}", statement)),
+        },
+        None => (0, format!("{}\n", statement)),
+    };
+    let position = offset_to_position(content, offset);
+    TextEdit {
+        range: Range {
+            start: position,
+            end: position,
+        },
+        new_text,
+    }
+}
+
+/// 从 `from_dir` 到 `to` 的相对路径，使用 `/` 分隔，作为导入字符串
+fn relative_import_path(from_dir: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from_dir.components().collect();
+    let target: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&target).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(
+        target[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    );
+    parts.join("/")
+}
+
+/// 校验诊断仍指向源码中的同名标识符（文档可能已被修改），返回其字节偏移和名字
+fn locate_unused(content: &str, diagnostic: &Diagnostic) -> Option<(usize, String)> {
+    if diagnostic.code != Some(NumberOrString::String(UNUSED_VARIABLE_CODE.to_string())) {
+        return None;
+    }
+    let name = diagnostic.data.as_ref()?.get("name")?.as_str()?;
+    let start = position_to_offset(content, diagnostic.range.start)?;
+    if !content.get(start..)?.starts_with(name) || name.starts_with('_') {
+        return None;
+    }
+    Some((start, name.to_string()))
+}
+
+fn prefix_edit(content: &str, start: usize) -> TextEdit {
+    let position = offset_to_position(content, start);
+    TextEdit {
+        range: Range {
+            start: position,
+            end: position,
+        },
+        new_text: "_".to_string(),
+    }
+}
+
+fn quick_fix(
+    title: String,
+    uri: &Url,
+    edits: Vec<TextEdit>,
+    diagnostics: Vec<Diagnostic>,
+    is_preferred: bool,
+) -> CodeActionOrCommand {
+    CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(diagnostics),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        is_preferred: Some(is_preferred),
+        ..Default::default()
+    })
+}
+
+/// 绑定可以安全删除时返回要删除的范围
+///
+/// 只处理 `let [constraint] name[: T] = value;` 这种单一绑定的语句，且值中不能有
+/// 函数应用、`panic`、赋值或宏调用等可能产生副作用的部分。若语句独占若干整行，
+/// 连同这些行一起删除。
+fn removable_binding(content: &str, tokens: &[Token], name_start: usize) -> Option<Range> {
+    let name = tokens.iter().position(|t| t.span.start == name_start)?;
+    let mut let_index = name.checked_sub(1)?;
+    if tokens[let_index].text == "constraint" {
+        let_index = let_index.checked_sub(1)?;
+    }
+    if tokens[name].kind != TokenKind::Ident || tokens[let_index].text != "let" {
+        return None;
+    }
+    if !tokens
+        .get(name + 1)
+        .is_some_and(|t| t.is_punct(":") || t.is_punct("="))
+    {
+        return None;
+    }
+
+    let end = statement_end(tokens, let_index + 1, false);
+    if !tokens.get(end.checked_sub(1)?)?.is_punct(";") {
+        return None;
+    }
+    let assign = (name + 1..end).find(|&i| tokens[i].is_punct("="))?;
+    if has_effects(&tokens[assign + 1..end - 1]) {
+        return None;
+    }
+
+    let mut start = tokens[let_index].span.start;
+    let mut stop = tokens[end - 1].span.end;
+    let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[stop..]
+        .find('\n')
+        .map_or(content.len(), |i| stop + i + 1);
+    if content[line_start..start].trim().is_empty() && content[stop..line_end].trim().is_empty() {
+        start = line_start;
+        stop = line_end;
+    }
+
+    Some(Range {
+        start: offset_to_position(content, start),
+        end: offset_to_position(content, stop),
+    })
+}
+
+/// 保守地判断值表达式是否可能有副作用：任何函数应用（括号或并置）、`panic`、
+/// `:=` 赋值或 `name!` 宏调用都视为有副作用
+fn has_effects(value: &[Token]) -> bool {
+    let is_operand = |t: &Token| {
+        matches!(
+            t.kind,
+            TokenKind::Ident | TokenKind::Number | TokenKind::String | TokenKind::Char
+        ) || t.is_punct(")")
+            || t.is_punct("]")
+    };
+
+    value.iter().enumerate().any(|(i, token)| {
+        let previous = i.checked_sub(1).map(|p| &value[p]);
+        token.text == "panic"
+            || token.is_punct(":=")
+            || token.is_punct("!")
+            || (token.is_punct("(") && previous.is_some_and(is_operand))
+            || (token.kind == TokenKind::Ident && previous.is_some_and(is_operand))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Position;
+
+    fn uri() -> Url {
+        Url::parse("file:///workspace/main.mu").unwrap()
+    }
+
+    /// 指向 `let constraint <name>:` 中名字的未使用变量诊断
+    fn unused(content: &str, name: &str) -> Diagnostic {
+        let start = content.find(&format!(" {}:", name)).unwrap() + 1;
+        let start = offset_to_position(content, start);
+        Diagnostic {
+            range: Range {
+                start,
+                end: Position::new(start.line, start.character + name.len() as u32),
+            },
+            code: Some(NumberOrString::String(UNUSED_VARIABLE_CODE.to_string())),
+            data: Some(serde_json::json!({ "name": name })),
+            ..Default::default()
+        }
+    }
+
+    fn titles_and_edits(actions: &[CodeActionOrCommand]) -> Vec<(&str, Vec<TextEdit>)> {
+        actions
+            .iter()
+            .map(|action| {
+                let CodeActionOrCommand::CodeAction(action) = action else {
+                    panic!("expected a code action");
+                };
+                let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+                (action.title.as_str(), changes[&uri()].clone())
+            })
+            .collect()
+    }
+
+    fn insert(line: u32, character: u32, text: &str) -> TextEdit {
+        TextEdit {
+            range: Range {
+                start: Position::new(line, character),
+                end: Position::new(line, character),
+            },
+            new_text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn unused_binding_gets_prefix_and_removal() {
+        let content = "let constraint a: nat = 1;\nlet constraint unused: nat = (a, 2);\na\n";
+        let diagnostic = unused(content, "unused");
+        let actions = unused_variable_actions(
+            &uri(),
+            content,
+            std::slice::from_ref(&diagnostic),
+            std::slice::from_ref(&diagnostic),
+        );
+        assert_eq!(
+            titles_and_edits(&actions),
+            [
+                (
+                    "Prefix `unused` with an underscore",
+                    vec![insert(1, 15, "_")]
+                ),
+                (
+                    "Remove unused binding `unused`",
+                    vec![TextEdit {
+                        range: Range {
+                            start: Position::new(1, 0),
+                            end: Position::new(2, 0),
+                        },
+                        new_text: String::new(),
+                    }]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn bindings_with_side_effects_are_not_removable() {
+        for value in [
+            "f(1)",
+            "f x",
+            "panic",
+            "print!('a')",
+            "{ r := 1; r }",
+            "(1, g(2))",
+        ] {
+            let content = format!("let constraint unused: any = {};\n", value);
+            let diagnostic = unused(&content, "unused");
+            let actions =
+                unused_variable_actions(&uri(), &content, &[diagnostic.clone()], &[diagnostic]);
+            let titles: Vec<_> = titles_and_edits(&actions)
+                .into_iter()
+                .map(|(title, _)| title)
+                .collect();
+            assert_eq!(titles, ["Prefix `unused` with an underscore"], "{}", value);
+        }
+    }
+
+    #[test]
+    fn prefix_all_covers_every_unused_variable() {
+        let content = "let constraint a: nat = 1;\nlet constraint b: nat = 2;\n()\n";
+        let (a, b) = (unused(content, "a"), unused(content, "b"));
+        let actions =
+            unused_variable_actions(&uri(), content, &[a.clone()], &[a.clone(), b.clone()]);
+        let actions = titles_and_edits(&actions);
+        assert_eq!(actions.len(), 3);
+        assert_eq!(
+            actions[2],
+            (
+                "Prefix all unused variables in file with an underscore",
+                vec![insert(0, 15, "_"), insert(1, 15, "_")]
+            )
+        );
+
+        // 光标处没有未使用变量诊断时不提供
+        assert!(unused_variable_actions(&uri(), content, &[], &[a, b]).is_empty());
+    }
+
+    #[test]
+    fn stale_or_prefixed_diagnostics_are_ignored() {
+        let content = "let constraint a: nat = 1;\n()\n";
+        let diagnostic = unused(content, "a");
+        // 文档已被修改，诊断位置不再指向 `a`
+        let edited = "let constraint b: nat = 1;\n()\n";
+        assert!(unused_variable_actions(&uri(), edited, &[diagnostic.clone()], &[]).is_empty());
+
+        let prefixed = "let constraint _a: nat = 1;\n()\n";
+        let diagnostic = unused(prefixed, "_a");
+        assert!(unused_variable_actions(&uri(), prefixed, &[diagnostic], &[]).is_empty());
+    }
+
+    /// 临时目录下的 `list.mu` 与 `other.mu`，返回目录的规范路径
+    fn fixture_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mutica-lsp-code-actions-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["list.mu", "other.mu"] {
+            std::fs::write(dir.join(file), "()").unwrap();
+        }
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn relative_paths_between_sibling_and_nested_directories() {
+        let from = Path::new("/workspace/src/app");
+        assert_eq!(
+            relative_import_path(from, Path::new("/workspace/src/app/list.mu")),
+            "list.mu"
+        );
+        assert_eq!(
+            relative_import_path(from, Path::new("/workspace/src/app/util/list.mu")),
+            "util/list.mu"
+        );
+        assert_eq!(
+            relative_import_path(from, Path::new("/workspace/src/lib/list.mu")),
+            "../lib/list.mu"
+        );
+        assert_eq!(
+            relative_import_path(from, Path::new("/workspace/lib/util/list.mu")),
+            "../../lib/util/list.mu"
+        );
+    }
+
+    #[test]
+    fn existing_import_block_is_extended() {
+        let dir = fixture_dir("extend");
+        let list = dir.join("list.mu");
+        let content = "\
+let constraint {
+    length::(length: any)
+} = import \"list.mu\";
+length
+";
+        let tokens: Vec<Token> = Lexer::new(content).collect();
+        assert_eq!(
+            extend_import_block(content, &tokens, &list, "filter", &dir, &[]),
+            Some(insert(1, 25, " &\n    filter::(filter: any)"))
+        );
+        // 块来自其他模块时不扩展
+        let other = dir.join("other.mu");
+        assert_eq!(
+            extend_import_block(content, &tokens, &other, "filter", &dir, &[]),
+            None
+        );
+
+        // 右侧是绑定到 `import` 的变量
+        let aliased = "\
+let constraint list_pkg = import \"list.mu\";
+let constraint { length::(length: any) } = list_pkg;
+length
+";
+        let tokens: Vec<Token> = Lexer::new(aliased).collect();
+        assert_eq!(
+            extend_import_block(aliased, &tokens, &list, "filter", &dir, &[]),
+            Some(insert(1, 38, " &\n    filter::(filter: any)"))
+        );
+    }
+
+    #[test]
+    fn new_import_goes_after_the_last_import() {
+        let content = "\
+let constraint list_pkg = import \"list.mu\";
+let constraint x: nat = 1;
+x
+";
+        let tokens: Vec<Token> = Lexer::new(content).collect();
+        assert_eq!(
+            insert_import(content, &tokens, "map", "util/map.mu"),
+            insert(
+                1,
+                0,
+                "let constraint map::(map: any) = import \"util/map.mu\";\n"
+            )
+        );
+
+        // 没有导入时插入到文件开头
+        let content = "let constraint x: nat = 1;\nx";
+        let tokens: Vec<Token> = Lexer::new(content).collect();
+        assert_eq!(
+            insert_import(content, &tokens, "map", "map.mu"),
+            insert(
+                0,
+                0,
+                "let constraint map::(map: any) = import \"map.mu\";\n"
+            )
+        );
+    }
+}
@@ -1,114 +1,250 @@
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
-use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, Position, Url};
+use tower_lsp::lsp_types::{
+    Command, CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionTextEdit,
+    InsertTextFormat, Position, Range, TextEdit, Url,
+};
 
-pub fn get_completion_items() -> Vec<CompletionItem> {
-    let keywords = vec![
-        "let",
-        "with",
-        "match",
-        "rec",
-        "loop",
-        "panic",
-        "nat",
-        "char",
-        "float",
-        "true",
-        "false",
-        "any",
-        "unknown",
-        "never",
-        "import",
-        "if",
-        "then",
-        "else",
-        "handle",
-        "type",
-        "is",
-        "for",
-        "in",
-        "extend",
-        "sub",
-        "dyn_rec",
-        "where",
-        "exist",
-        "assert",
-        "constraint",
-        "mut",
-        "delay",
-        "typeof",
-    ];
-
-    let operators = vec![
-        "->", "|->", "=>", "::", ".", "@", "|", "!", ":", "~", ",", "&", "==", "!=", "<", "<=",
-        ">", ">=", "+", "-", "*", "/", "%", "=", ";", "#", "\\", "(", ")", "[", "]", "{", "}",
-        "|>", "..", ":=",
-    ];
-
-    let functions = vec![
-        "input!",
-        "print!",
-        "println!",
-        "flush!",
-        "panic_with!",
-        "repr!",
-        "display!",
-        "perform!",
-        "stopwatch!",
-        "__add!",
-        "__sub!",
-        "__mul!",
-        "__div!",
-        "__mod!",
-        "__is!",
-        "__greater!",
-        "__less!",
-        "__opcode!",
-        "__neg!",
-        "__assign!",
-        "__set_fixpoint!",
-        "__build_fixpoint!",
-        "__typeof",
-    ];
+use crate::lsp::document::Document;
+use crate::lsp::imports::{module_aliases, resolved_imports};
+use crate::lsp::intrinsics;
+use crate::lsp::lexer::{Lexer, Token, TokenKind, is_ident_continue};
+use crate::lsp::symbols::exported_labels;
+use crate::lsp::utils::{offset_to_position, position_to_offset};
+use crate::lsp::workspace::IGNORED_DIRS;
 
-    let mut items = Vec::new();
+/// 补全位置所处的语法上下文
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionContext {
+    /// `constraint`/`assert` 之后或 match 分支开头的模式，包括其中的类型标注
+    Pattern,
+    /// 其余可以书写表达式的位置
+    Expression,
+}
 
-    for kw in keywords {
-        items.push(CompletionItem {
-            label: kw.to_string(),
-            kind: Some(CompletionItemKind::KEYWORD),
-            ..Default::default()
-        });
+/// 模式中可用的类型约束
+const TYPE_KEYWORDS: &[&str] = &[
+    "any", "nat", "char", "float", "unknown", "never", "true", "false",
+];
+
+/// 引入绑定或字面量匹配的模式关键字
+const BINDER_KEYWORDS: &[&str] = &["constraint", "assert"];
+
+const EXPRESSION_KEYWORDS: &[&str] = &[
+    "let",
+    "with",
+    "match",
+    "rec",
+    "loop",
+    "panic",
+    "import",
+    "if",
+    "then",
+    "else",
+    "handle",
+    "type",
+    "is",
+    "for",
+    "in",
+    "extend",
+    "sub",
+    "dyn_rec",
+    "where",
+    "exist",
+    "assert",
+    "constraint",
+    "mut",
+    "delay",
+    "typeof",
+];
+
+/// 判断光标处的补全上下文，位于注释或字符串中时返回 None
+pub fn completion_context(content: &str, position: Position) -> Option<CompletionContext> {
+    let offset = position_to_offset(content, position)?;
+    let tokens: Vec<Token> = Lexer::new(content).collect();
+
+    let inside_literal = tokens.iter().any(|t| {
+        // 未闭合的字符串、字符、引号标识符或块注释一直延伸到文件末尾
+        let unclosed = t.kind == TokenKind::Unknown
+            && ["\"", "'", "$\"", "/*"]
+                .iter()
+                .any(|p| t.text.starts_with(p));
+        match t.kind {
+            TokenKind::LineComment => t.span.start < offset && offset <= t.span.end,
+            TokenKind::BlockComment | TokenKind::String | TokenKind::Char => {
+                t.span.start < offset && offset < t.span.end
+            }
+            _ if unclosed => t.span.start < offset && offset <= t.span.end,
+            _ => false,
+        }
+    });
+    if inside_literal {
+        return None;
     }
 
-    for op in operators {
-        items.push(CompletionItem {
-            label: op.to_string(),
-            kind: Some(CompletionItemKind::OPERATOR),
-            ..Default::default()
-        });
+    // 正在输入的标识符不参与判断
+    let word_start = content[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_ident_continue(c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let code: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| !t.is_comment() && t.span.end <= word_start)
+        .collect();
+    Some(if in_pattern(&code) {
+        CompletionContext::Pattern
+    } else {
+        CompletionContext::Expression
+    })
+}
+
+/// 扫描光标之前的 token，判断最后是否停留在模式中
+///
+/// 模式从 `constraint`、`assert` 或 match 分支的 `|` 开始，
+/// 到同一括号深度的 `=`、`=>`、`:=`、`;`、`,`、`in` 或外层闭括号结束。
+fn in_pattern(tokens: &[&Token]) -> bool {
+    let mut depth = 0usize;
+    // 尚未结束的 match 所在的括号深度
+    let mut matches: Vec<usize> = Vec::new();
+    let mut pattern: Option<usize> = None;
+
+    for token in tokens {
+        match (token.kind, token.text) {
+            (TokenKind::Punct, "(" | "[" | "{") => depth += 1,
+            (TokenKind::Punct, ")" | "]" | "}") => {
+                depth = depth.saturating_sub(1);
+                matches.retain(|&d| d <= depth);
+                if pattern.is_some_and(|d| d > depth) {
+                    pattern = None;
+                }
+            }
+            (TokenKind::Punct, ";" | ",") => {
+                matches.retain(|&d| d != depth);
+                if pattern == Some(depth) {
+                    pattern = None;
+                }
+            }
+            (TokenKind::Punct, "=" | "=>" | ":=") | (TokenKind::Keyword, "in")
+                if pattern == Some(depth) =>
+            {
+                pattern = None;
+            }
+            (TokenKind::Punct, "|") if pattern.is_none() && matches.last() == Some(&depth) => {
+                pattern = Some(depth);
+            }
+            (TokenKind::Keyword, "constraint" | "assert") if pattern.is_none() => {
+                pattern = Some(depth);
+            }
+            (TokenKind::Keyword, "match") => matches.push(depth),
+            _ => {}
+        }
     }
+    pattern.is_some()
+}
 
-    for func in functions {
-        items.push(CompletionItem {
-            label: func.to_string(),
-            kind: Some(CompletionItemKind::FUNCTION),
-            ..Default::default()
-        });
+/// 按上下文生成关键字与内建函数补全项，不包含运算符
+///
+/// 客户端支持 snippet 时，表达式位置还会加入代码片段，并省去与片段同名的关键字项。
+pub fn get_completion_items(context: CompletionContext, snippets: bool) -> Vec<CompletionItem> {
+    let keyword = |kw: &&str| CompletionItem {
+        label: kw.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        ..Default::default()
+    };
+
+    match context {
+        CompletionContext::Pattern => BINDER_KEYWORDS
+            .iter()
+            .chain(TYPE_KEYWORDS)
+            .map(keyword)
+            .collect(),
+        CompletionContext::Expression => {
+            let mut items: Vec<CompletionItem> = EXPRESSION_KEYWORDS
+                .iter()
+                .chain(TYPE_KEYWORDS)
+                .filter(|kw| !(snippets && SNIPPETS.iter().any(|(label, ..)| label == *kw)))
+                .map(keyword)
+                .collect();
+            // 内建函数在 completionItem/resolve 时补充说明与签名
+            items.extend(intrinsics::names().map(|name| CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                ..Default::default()
+            }));
+            if snippets {
+                items.extend(snippet_completions());
+            }
+            items
+        }
     }
+}
 
-    items
+/// 常用结构的代码片段：(标签, 说明, 片段正文)
+const SNIPPETS: &[(&str, &str, &str)] = &[
+    (
+        "match",
+        "match scaffold ending in `| panic`",
+        "match ${1:value}\n    | constraint ${2:x}: ${3:any} => ${4:expr}\n    | panic",
+    ),
+    (
+        "loop",
+        "named loop with its recursive call",
+        "loop ${1:go}: constraint ${2:x}: ${3:T} = ${4:init};\nif ${5:done} then ${2:x} else ${1:go}(${6:next})",
+    ),
+    (
+        "let fn",
+        "function binding",
+        "let constraint ${1:name}: any = constraint ${2:x}: any => $0;",
+    ),
+    (
+        "dyn_rec",
+        "dynamically recursive binding",
+        "dyn_rec ${1:name}: $0",
+    ),
+    (
+        "if",
+        "if-then-else expression",
+        "if ${1:cond} then ${2:expr} else ${3:expr}",
+    ),
+    (
+        "@iter",
+        "iterate over a list",
+        "@iter constraint ${1:c}: ${2:T} = ${3:xs} in {\n    $0\n}",
+    ),
+    (
+        "extend",
+        "operator overload skeleton",
+        "extend \\$\"op#${1:add}\": match\n    | constraint (${2:x}: ${3:T}, ${4:y}: ${3:T}) => $0\n    | panic;",
+    ),
+];
+
+/// 代码片段补全项，仅在客户端声明支持 snippet 时发送
+fn snippet_completions() -> Vec<CompletionItem> {
+    SNIPPETS
+        .iter()
+        .map(|(label, detail, body)| CompletionItem {
+            label: label.to_string(),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some(detail.to_string()),
+            insert_text: Some(body.to_string()),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..Default::default()
+        })
+        .collect()
 }
 
 /// 根据变量映射提取变量补全项
 pub fn get_variable_completions(
     uri: &Url,
     position: Position,
-    documents: &RwLock<HashMap<Url, String>>,
+    documents: &RwLock<HashMap<Url, Document>>,
     variable_maps: &RwLock<HashMap<Url, Vec<Option<Vec<String>>>>>,
 ) -> Option<Vec<CompletionItem>> {
     // 获取文档内容
-    let content = documents.read().ok()?.get(uri).cloned()?;
+    let content = documents.read().ok()?.get(uri)?.text();
 
     // 计算字节偏移
     let mut byte_offset = position_to_offset(&content, position)?;
@@ -145,30 +281,296 @@ pub fn get_variable_completions(
     Some(items)
 }
 
-/// 将 Position 转换为字节偏移
-fn position_to_offset(content: &str, position: Position) -> Option<usize> {
-    let mut byte_offset = 0;
+/// 导入解构模式中的标签补全
+///
+/// 光标位于 `let constraint { | } = import "list.mu";` 或 `let constraint X::(X: any) = list_pkg;`
+/// 的模式部分时，列出被导入模块导出链中尚未解构的标签，并插入完整的 `Label::(Label: any)`。
+/// `read_module` 按导入路径读取模块内容。
+pub fn import_label_completions(
+    content: &str,
+    position: Position,
+    read_module: impl Fn(&str) -> Option<String>,
+) -> Option<Vec<CompletionItem>> {
+    let offset = position_to_offset(content, position)?;
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let (spec, pattern) = destructured_import(&tokens, offset)?;
+    let module = read_module(spec)?;
+
+    let destructured: HashSet<&str> = pattern
+        .windows(2)
+        .filter(|pair| pair[1].is_punct("::"))
+        .map(|pair| pair[0].text)
+        .collect();
+
+    // 替换光标所在的（部分输入的）标签
+    let start = content[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_ident_continue(c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = content[offset..]
+        .char_indices()
+        .find(|&(_, c)| !is_ident_continue(c))
+        .map_or(content.len(), |(i, _)| offset + i);
+    let range = Range::new(
+        offset_to_position(content, start),
+        offset_to_position(content, end),
+    );
+    let typed = &content[start..end];
+
+    let items = exported_labels(&module)
+        .into_iter()
+        .filter(|label| label == typed || !destructured.contains(label.as_str()))
+        .map(|label| CompletionItem {
+            kind: Some(CompletionItemKind::FIELD),
+            detail: Some(format!("exported by \"{}\"", spec)),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                range,
+                new_text: format!("{}::({}: any)", label, label),
+            })),
+            filter_text: Some(label.clone()),
+            label,
+            ..Default::default()
+        })
+        .collect();
+    Some(items)
+}
+
+/// 光标是否位于绑定导入模块的 `let` 模式中，返回 (导入路径, 模式的 token)
+///
+/// 值可以是 `import "..."`，也可以是绑定了导入的别名；带类型标注的普通绑定不算。
+fn destructured_import<'a, 'b>(
+    tokens: &'b [Token<'a>],
+    offset: usize,
+) -> Option<(&'a str, &'b [Token<'a>])> {
+    let start = tokens
+        .iter()
+        .rposition(|t| t.span.end <= offset && t.kind == TokenKind::Keyword && t.text == "let")?;
+
+    let mut depth = 0usize;
+    let mut assign = None;
+    for (i, token) in tokens.iter().enumerate().skip(start + 1) {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" if depth == 0 => return None,
+            ")" | "]" | "}" => depth -= 1,
+            ":" if depth == 0 => return None,
+            "=" if depth == 0 => {
+                assign = Some(i);
+                break;
+            }
+            ";" => return None,
+            _ => {}
+        }
+    }
+    let assign = assign.filter(|&i| tokens[i].span.start >= offset)?;
+
+    let spec = match &tokens[assign + 1..] {
+        [keyword, string, ..] if keyword.text == "import" && string.kind == TokenKind::String => {
+            &string.text[1..string.text.len() - 1]
+        }
+        [alias, end, ..] if alias.kind == TokenKind::Ident && end.is_punct(";") => {
+            module_aliases(tokens).get(alias.text).copied()?
+        }
+        _ => return None,
+    };
+    Some((spec, &tokens[start + 1..assign]))
+}
+
+/// `import "..."` 字符串中的路径补全
+///
+/// 依次在文档所在目录和 `search_paths` 中列出与已输入路径匹配的子目录和 `.mu` 文件，
+/// 选中目录后会重新触发补全以便继续逐级进入；当前文件已导入的模块会被标出并排在后面。
+/// 光标不在导入路径字符串内时返回 None。
+pub fn import_path_completions(
+    content: &str,
+    position: Position,
+    file_path: &Path,
+    search_paths: &[PathBuf],
+) -> Option<Vec<CompletionItem>> {
+    let offset = position_to_offset(content, position)?;
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let i = tokens.iter().position(|t| {
+        // 未闭合的字符串被词法分析为 Unknown
+        matches!(t.kind, TokenKind::String | TokenKind::Unknown)
+            && t.text.starts_with('"')
+            && t.span.start < offset
+            && (offset < t.span.end || (offset == t.span.end && t.kind == TokenKind::Unknown))
+    })?;
+    if i == 0 || tokens[i - 1].kind != TokenKind::Keyword || tokens[i - 1].text != "import" {
+        return None;
+    }
+
+    // 只替换最后一级路径：从最后一个 `/` 到下一个 `/` 或闭引号
+    let string = &tokens[i];
+    let typed = &content[string.span.start + 1..offset];
+    let (dir, segment_start) = match typed.rfind('/') {
+        Some(slash) => (&typed[..=slash], offset - typed.len() + slash + 1),
+        None => ("", offset - typed.len()),
+    };
+    let rest = &content[offset..string.span.end];
+    let segment_end = offset + rest.find(['/', '"']).unwrap_or(rest.len());
+    let range = Range::new(
+        offset_to_position(content, segment_start),
+        offset_to_position(content, segment_end),
+    );
 
-    for (current_line, line) in content.split('\n').enumerate() {
-        if current_line == position.line as usize {
-            // 找到目标行，计算列偏移
-            let desired = position.character as usize;
+    let base_dir = file_path.parent().unwrap_or(Path::new("."));
+    let imported: HashSet<PathBuf> = resolved_imports(content, file_path, search_paths)
+        .into_iter()
+        .map(|(_, target)| target)
+        .collect();
+    let current = file_path.canonicalize().ok();
 
-            // 如果 desired 为 0，则在行首；否则尝试取第 desired 个字符的字节索引，超出则为行尾
-            let col_offset = if desired == 0 {
-                0
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    for root in std::iter::once(base_dir).chain(search_paths.iter().map(PathBuf::as_path)) {
+        let Ok(entries) = std::fs::read_dir(root.join(dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path().canonicalize().unwrap_or(entry.path());
+            let is_dir = file_type.is_dir() || (file_type.is_symlink() && path.is_dir());
+            if is_dir {
+                if name.starts_with('.') || IGNORED_DIRS.contains(&name.as_str()) {
+                    continue;
+                }
+            } else if !name.ends_with(".mu") || current.as_ref() == Some(&path) {
+                continue;
+            }
+            // 同名条目以先搜索到的为准，与导入解析的顺序一致
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+
+            let already_imported = imported.contains(&path);
+            let (kind, new_text, command, order) = if is_dir {
+                let command = Command::new(
+                    "Trigger Suggest".to_string(),
+                    "editor.action.triggerSuggest".to_string(),
+                    None,
+                );
+                (
+                    CompletionItemKind::FOLDER,
+                    format!("{}/", name),
+                    Some(command),
+                    0,
+                )
+            } else if already_imported {
+                (CompletionItemKind::FILE, name.clone(), None, 2)
             } else {
-                line.char_indices()
-                    .nth(desired)
-                    .map(|(i, _)| i)
-                    .unwrap_or(line.len())
+                (CompletionItemKind::FILE, name.clone(), None, 1)
             };
+            items.push(CompletionItem {
+                kind: Some(kind),
+                detail: Some(path.display().to_string()),
+                label_details: already_imported.then(|| CompletionItemLabelDetails {
+                    detail: None,
+                    description: Some("already imported".to_string()),
+                }),
+                sort_text: Some(format!("{}{}", order, name)),
+                filter_text: Some(name.clone()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit { range, new_text })),
+                command,
+                label: name,
+                ..Default::default()
+            });
+        }
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            return Some(byte_offset + col_offset);
+    fn context_at_end(content: &str) -> Option<CompletionContext> {
+        completion_context(content, offset_to_position(content, content.len()))
+    }
+
+    #[test]
+    fn binders_and_annotations_are_patterns() {
+        for content in [
+            "let constraint ",
+            "let constraint x: ",
+            "let constraint x: na",
+            "let constraint (a: nat, ",
+            "assert ",
+            "match v\n    | ",
+            "match v\n    | constraint x: nat => x\n    | ",
+        ] {
+            assert_eq!(
+                context_at_end(content),
+                Some(CompletionContext::Pattern),
+                "{:?}",
+                content
+            );
         }
+    }
 
-        byte_offset += line.len() + 1; // +1 for '\n'
+    #[test]
+    fn values_and_arm_bodies_are_expressions() {
+        for content in [
+            "let constraint x: nat = ",
+            "let constraint x: nat = 1;\n",
+            "let constraint f: any = constraint x: nat => ",
+            "match v\n    | constraint x: nat => ",
+            "a | ",
+            "(constraint x: nat) ",
+        ] {
+            assert_eq!(
+                context_at_end(content),
+                Some(CompletionContext::Expression),
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn no_context_in_comments_or_strings() {
+        assert_eq!(context_at_end("// constraint "), None);
+        assert_eq!(context_at_end("/* let "), None);
+        assert_eq!(context_at_end("let s: any = \"ab"), None);
+        assert_eq!(context_at_end("let $\"op#"), None);
     }
 
-    None
+    #[test]
+    fn snippets_replace_keywords_with_the_same_label() {
+        let labels = |items: &[CompletionItem]| -> Vec<String> {
+            items.iter().map(|item| item.label.clone()).collect()
+        };
+
+        let items = get_completion_items(CompletionContext::Expression, true);
+        let mut seen = HashSet::new();
+        for label in labels(&items) {
+            assert!(seen.insert(label.clone()), "duplicate label {}", label);
+        }
+        for label in ["if", "match", "loop", "dyn_rec", "extend"] {
+            let item = items.iter().find(|item| item.label == label).unwrap();
+            assert_eq!(item.kind, Some(CompletionItemKind::SNIPPET));
+        }
+
+        // 不支持 snippet 时保留普通关键字
+        let items = get_completion_items(CompletionContext::Expression, false);
+        let item = items.iter().find(|item| item.label == "if").unwrap();
+        assert_eq!(item.kind, Some(CompletionItemKind::KEYWORD));
+        assert!(
+            items
+                .iter()
+                .all(|item| item.kind != Some(CompletionItemKind::SNIPPET))
+        );
+
+        // 模式位置没有代码片段
+        let items = get_completion_items(CompletionContext::Pattern, true);
+        assert!(labels(&items).iter().all(|label| label != "match"));
+    }
 }
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent, Url};
+
+use crate::lsp::imports::{import_specifiers, resolve_import};
+
+/// 编辑器中打开的文档，使用 rope 存储以便高效地应用增量修改
+#[derive(Debug, Clone)]
+pub struct Document {
+    rope: Rope,
+    version: i32,
+    /// 打开时规范化的文件路径，非文件 URI 为 None
+    path: Option<PathBuf>,
+}
+
+impl Document {
+    pub fn new(uri: &Url, text: &str, version: i32) -> Self {
+        let path = uri
+            .to_file_path()
+            .ok()
+            .map(|path| path.canonicalize().unwrap_or(path));
+        Self {
+            rope: Rope::from_str(text),
+            version,
+            path,
+        }
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn text(&self) -> String {
+        self.rope.to_string()
+    }
+
+    /// 按顺序应用一次 `didChange` 通知中的所有修改，并更新版本号
+    pub fn apply_changes(&mut self, changes: Vec<TextDocumentContentChangeEvent>, version: i32) {
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = self.position_to_char(range.start);
+                    let end = self.position_to_char(range.end).max(start);
+                    self.rope.remove(start..end);
+                    self.rope.insert(start, &change.text);
+                }
+                None => self.rope = Rope::from_str(&change.text),
+            }
+        }
+        self.version = version;
+    }
+
+    /// 将 LSP 位置（UTF-16 列号）转换为 rope 中的字符索引
+    /// 超出行尾的列号按行尾处理，超出文档的行号按文档末尾处理
+    fn position_to_char(&self, position: Position) -> usize {
+        let line = position.line as usize;
+        if line >= self.rope.len_lines() {
+            return self.rope.len_chars();
+        }
+
+        let line_start = self.rope.line_to_char(line);
+        let line_len = self
+            .rope
+            .line(line)
+            .chars()
+            .take_while(|ch| *ch != '\n' && *ch != '\r')
+            .count();
+
+        let line_start_utf16 = self.rope.char_to_utf16_cu(line_start);
+        let target = (line_start_utf16 + position.character as usize).min(self.rope.len_utf16_cu());
+        let char_idx = self.rope.utf16_cu_to_char(target);
+        char_idx.min(line_start + line_len)
+    }
+}
+
+/// 按规范化路径查找打开的文档
+pub fn find_open_document<'a>(
+    documents: &'a HashMap<Url, Document>,
+    canonical: &Path,
+) -> Option<&'a Document> {
+    documents
+        .values()
+        .find(|document| document.path() == Some(canonical))
+}
+
+/// 读取模块内容：打开的文档使用编辑器中的版本，否则从磁盘读取
+pub fn module_source(documents: &HashMap<Url, Document>, canonical: &Path) -> Option<String> {
+    match find_open_document(documents, canonical) {
+        Some(document) => Some(document.text()),
+        None => std::fs::read_to_string(canonical).ok(),
+    }
+}
+
+/// 一次分析使用的覆盖层，解析导入时编辑器中的内容优先于磁盘内容
+#[derive(Debug, Default)]
+pub struct Overlay {
+    /// 打开的文档内容，按规范化路径索引
+    pub contents: HashMap<PathBuf, String>,
+    /// 打开的文档版本，用于判断缓存的模块是否过期
+    pub versions: HashMap<PathBuf, i32>,
+    /// 从分析的文件沿导入可达的所有模块（含文件本身）
+    pub modules: HashSet<PathBuf>,
+}
+
+/// 分析 `root` 时使用的覆盖层，只包含从 `root` 沿导入可达的打开文档
+///
+/// 打开的模块按编辑器中的内容查找导入；其余模块使用 `known_imports`（模块缓存记录的导入），
+/// 缓存中没有时才读取磁盘。
+pub fn buffer_overlay(
+    documents: &HashMap<Url, Document>,
+    root: &Path,
+    search_paths: &[PathBuf],
+    known_imports: impl Fn(&Path) -> Option<Vec<PathBuf>>,
+) -> Overlay {
+    let open: HashMap<&Path, &Document> = documents
+        .values()
+        .filter_map(|document| Some((document.path()?, document)))
+        .collect();
+
+    let mut overlay = Overlay::default();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if !overlay.modules.insert(path.clone()) {
+            continue;
+        }
+        let imports = match open.get(path.as_path()) {
+            Some(document) => {
+                let content = document.text();
+                let imports = module_imports(&path, &content, search_paths);
+                overlay.contents.insert(path.clone(), content);
+                overlay.versions.insert(path.clone(), document.version());
+                imports
+            }
+            None => match known_imports(&path) {
+                Some(imports) => imports,
+                None => match std::fs::read_to_string(&path) {
+                    Ok(content) => module_imports(&path, &content, search_paths),
+                    Err(_) => continue,
+                },
+            },
+        };
+        pending.extend(imports);
+    }
+    overlay
+}
+
+fn module_imports(path: &Path, content: &str, search_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    import_specifiers(content)
+        .iter()
+        .filter_map(|import| resolve_import(&import.path, base_dir, search_paths))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Range;
+
+    use super::*;
+    use crate::lsp::utils::{offset_to_position, position_to_offset};
+
+    /// (起点 (行, 列), 终点 (行, 列), 新文本)
+    type Edit<'a> = ((u32, u32), (u32, u32), &'a str);
+
+    fn apply(text: &str, changes: &[Edit]) -> String {
+        let uri = Url::parse("untitled:test.mu").unwrap();
+        let mut document = Document::new(&uri, text, 1);
+        let changes = changes
+            .iter()
+            .map(|&((start_line, start_col), (end_line, end_col), text)| {
+                TextDocumentContentChangeEvent {
+                    range: Some(Range::new(
+                        Position::new(start_line, start_col),
+                        Position::new(end_line, end_col),
+                    )),
+                    range_length: None,
+                    text: text.to_string(),
+                }
+            })
+            .collect();
+        document.apply_changes(changes, 2);
+        assert_eq!(document.version(), 2);
+        document.text()
+    }
+
+    #[test]
+    fn multi_line_edits_apply_in_order() {
+        let text = "let a = 1;\nlet b = 2;\nlet c = 3;\n";
+        // 第二处修改的位置基于第一处修改之后的文本
+        let changes = [
+            ((0, 4), (1, 5), "x = 1;\nlet y"),
+            ((2, 0), (2, 0), "// c\n"),
+        ];
+        assert_eq!(
+            apply(text, &changes),
+            "let x = 1;\nlet y = 2;\n// c\nlet c = 3;\n"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let text = "a\r\nb\r\nc";
+        assert_eq!(apply(text, &[((1, 0), (1, 1), "B")]), "a\r\nB\r\nc");
+        // 超出行尾的列号停在 `\r\n` 之前
+        assert_eq!(apply(text, &[((0, 5), (0, 5), "!")]), "a!\r\nb\r\nc");
+        assert_eq!(apply(text, &[((0, 1), (1, 0), "")]), "ab\r\nc");
+    }
+
+    #[test]
+    fn lone_carriage_return_ends_a_line() {
+        let text = "a\rb\rc";
+        assert_eq!(apply(text, &[((2, 0), (2, 1), "C")]), "a\rb\rC");
+        assert_eq!(apply(text, &[((1, 1), (1, 1), "!")]), "a\rb!\rc");
+    }
+
+    #[test]
+    fn unicode_line_separators_are_not_line_breaks() {
+        let text = "let s = \"x\u{2028}y\u{85}z\u{c}\";\nlet t = 1;\n";
+        assert_eq!(
+            apply(text, &[((1, 4), (1, 5), "u")]),
+            "let s = \"x\u{2028}y\u{85}z\u{c}\";\nlet u = 1;\n"
+        );
+    }
+
+    #[test]
+    fn columns_count_utf16_code_units() {
+        // 😀 占两个 UTF-16 码元
+        let text = "let 😀 = 1;\nlet b = 😀;\n";
+        assert_eq!(
+            apply(text, &[((0, 4), (0, 6), "a")]),
+            "let a = 1;\nlet b = 😀;\n"
+        );
+        assert_eq!(
+            apply(text, &[((1, 10), (1, 10), "!")]),
+            "let 😀 = 1;\nlet b = 😀!;\n"
+        );
+    }
+
+    #[test]
+    fn utility_positions_agree_with_the_rope() {
+        // 诊断、语义 token 和悬停用 utils 中的换算，增量同步用 rope，两者的换行规则必须一致
+        let text = "a\r😀b\r\nc\nd\r\re";
+        for (offset, _) in text.char_indices() {
+            // `\r\n` 中间不是合法的编辑位置
+            if text[..offset].ends_with('\r') && text[offset..].starts_with('\n') {
+                continue;
+            }
+            let position = offset_to_position(text, offset);
+            assert_eq!(position_to_offset(text, position), Some(offset));
+            let (line, character) = (position.line, position.character);
+            let expected = format!("{}!{}", &text[..offset], &text[offset..]);
+            assert_eq!(
+                apply(text, &[((line, character), (line, character), "!")]),
+                expected
+            );
+        }
+    }
+}
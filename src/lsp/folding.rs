@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::ops::Range as ByteRange;
+
+use mutica::mutica_compiler::parser::{
+    WithLocation,
+    ast::{FlowedMetaData, LinearTypeAst},
+};
+use mutica::mutica_core::util::source_info::SourceFile;
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+
+use crate::lsp::ast_processor::child_nodes;
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::utils::offset_to_position;
+
+/// 收集当前文件中所有 match 表达式的字节范围
+pub fn collect_match_spans(
+    root: &WithLocation<LinearTypeAst, FlowedMetaData>,
+    spans: &mut Vec<ByteRange<usize>>,
+    source_file: &SourceFile,
+) {
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        if let LinearTypeAst::Match { .. } = node.value()
+            && let Some(loc) = node.location()
+            && loc.source() == source_file
+        {
+            spans.push(loc.span());
+        }
+        pending.extend(child_nodes(node));
+    }
+}
+
+/// 语法树中的节点范围，分析结果不对应当前版本时不可用
+pub struct SyntaxSpans<'a> {
+    /// 所有节点的范围，用于定位 `{}` 块
+    pub nodes: &'a [ByteRange<usize>],
+    pub matches: &'a [ByteRange<usize>],
+}
+
+/// 计算文档的折叠范围：`{}` 块、多行 match、连续的导入语句、多行块注释和 `// ====` 分节
+///
+/// `syntax` 为 None（解析失败或分析过期）时，块和 match 改用 token 扫描。
+pub fn folding_ranges(content: &str, syntax: Option<SyntaxSpans>) -> Vec<FoldingRange> {
+    let tokens: Vec<Token> = Lexer::new(content).collect();
+    let line_of = |offset: usize| offset_to_position(content, offset).line;
+    let mut ranges = Vec::new();
+    let mut push = |start: u32, end: u32, kind: Option<FoldingRangeKind>| {
+        if end > start {
+            ranges.push(FoldingRange {
+                start_line: start,
+                start_character: None,
+                end_line: end,
+                end_character: None,
+                kind,
+                collapsed_text: None,
+            });
+        }
+    };
+
+    let (blocks, match_spans) = match syntax {
+        Some(syntax) => (block_spans(&tokens, syntax.nodes), syntax.matches.to_vec()),
+        None => (bracket_blocks(&tokens), match_extents(&tokens)),
+    };
+    // `{}` 块：保留闭括号所在行
+    for block in blocks {
+        push(
+            line_of(block.start),
+            line_of(block.end).saturating_sub(1),
+            None,
+        );
+    }
+    for span in match_spans {
+        push(line_of(span.start), line_of(span.end), None);
+    }
+
+    for (start, end) in import_groups(content, &tokens) {
+        push(start, end, Some(FoldingRangeKind::Imports));
+    }
+
+    for comment in tokens.iter().filter(|t| t.kind == TokenKind::BlockComment) {
+        push(
+            line_of(comment.span.start),
+            line_of(comment.span.end),
+            Some(FoldingRangeKind::Comment),
+        );
+    }
+
+    for (start, end) in banner_sections(content, &tokens) {
+        push(start, end, Some(FoldingRangeKind::Region));
+    }
+
+    // 只去掉完全相同的范围，同一行开始的嵌套范围各自保留
+    let kind_order = |kind: &Option<FoldingRangeKind>| match kind {
+        None => 0,
+        Some(FoldingRangeKind::Comment) => 1,
+        Some(FoldingRangeKind::Imports) => 2,
+        Some(FoldingRangeKind::Region) => 3,
+    };
+    ranges.sort_by_key(|r| {
+        (
+            r.start_line,
+            std::cmp::Reverse(r.end_line),
+            kind_order(&r.kind),
+        )
+    });
+    ranges.dedup();
+    ranges
+}
+
+/// 由节点范围找出 `{}` 块，返回 `{` 的起点到 `}` 的起点
+///
+/// 节点范围可能包含花括号，也可能只覆盖括号内的表达式，两种情况都认作块。
+fn block_spans(tokens: &[Token], nodes: &[ByteRange<usize>]) -> Vec<ByteRange<usize>> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let starting: HashMap<usize, usize> = code
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.span.start, i))
+        .collect();
+    let ending: HashMap<usize, usize> = code
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.span.end, i))
+        .collect();
+
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let (&first, &last) = (starting.get(&node.start)?, ending.get(&node.end)?);
+            if code[first].is_punct("{") && code[last].is_punct("}") {
+                Some(code[first].span.start..code[last].span.start)
+            } else {
+                let (open, close) = (code.get(first.checked_sub(1)?)?, code.get(last + 1)?);
+                (open.is_punct("{") && close.is_punct("}"))
+                    .then_some(open.span.start..close.span.start)
+            }
+        })
+        .collect()
+}
+
+/// 解析失败时按括号配对估计 `{}` 块
+fn bracket_blocks(tokens: &[Token]) -> Vec<ByteRange<usize>> {
+    let mut blocks = Vec::new();
+    let mut open_braces = Vec::new();
+    for token in tokens.iter().filter(|t| !t.is_comment()) {
+        if token.is_punct("{") {
+            open_braces.push(token.span.start);
+        } else if token.is_punct("}")
+            && let Some(open) = open_braces.pop()
+        {
+            blocks.push(open..token.span.start);
+        }
+    }
+    blocks
+}
+
+/// 用 token 估计 match 表达式的范围：从 `match` 到同层的 `;`、`,` 或闭括号之前
+fn match_extents(tokens: &[Token]) -> Vec<ByteRange<usize>> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let mut extents = Vec::new();
+    for (i, token) in code.iter().enumerate() {
+        if token.kind != TokenKind::Keyword || token.text != "match" {
+            continue;
+        }
+        let mut depth = 0usize;
+        let mut end = token.span.end;
+        for next in &code[i + 1..] {
+            if next.is_punct("(") || next.is_punct("[") || next.is_punct("{") {
+                depth += 1;
+            } else if next.is_punct(")") || next.is_punct("]") || next.is_punct("}") {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            } else if depth == 0 && (next.is_punct(";") || next.is_punct(",")) {
+                break;
+            }
+            end = next.span.end;
+        }
+        extents.push(token.span.start..end);
+    }
+    extents
+}
+
+/// 连续的顶层导入语句（`let constraint ... = import "...";`），返回每组的起止行
+///
+/// 语句之间隔着空行、注释或其他语句时不算连续。
+fn import_groups(content: &str, tokens: &[Token]) -> Vec<(u32, u32)> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let line_of = |offset: usize| offset_to_position(content, offset).line;
+    let mut groups: Vec<(u32, u32)> = Vec::new();
+    let mut statement_start = 0;
+    let mut depth = 0usize;
+    let mut previous_is_import = false;
+
+    for (i, token) in code.iter().enumerate() {
+        if token.is_punct("(") || token.is_punct("[") || token.is_punct("{") {
+            depth += 1;
+        } else if token.is_punct(")") || token.is_punct("]") || token.is_punct("}") {
+            depth = depth.saturating_sub(1);
+        }
+        if depth > 0 || !token.is_punct(";") {
+            continue;
+        }
+
+        let statement = &code[statement_start..=i];
+        statement_start = i + 1;
+        let is_import = statement[0].text == "let"
+            && statement.windows(2).any(|pair| {
+                pair[0].kind == TokenKind::Keyword
+                    && pair[0].text == "import"
+                    && pair[1].kind == TokenKind::String
+            });
+        if !is_import {
+            previous_is_import = false;
+            continue;
+        }
+
+        let (start, end) = (line_of(statement[0].span.start), line_of(token.span.end));
+        match groups.last_mut() {
+            Some(group) if previous_is_import && group.1 + 1 == start => group.1 = end,
+            _ => groups.push((start, end)),
+        }
+        previous_is_import = true;
+    }
+    groups
+}
+
+/// `// ====` 分节注释：从分节标题到下一个分节标题之前（去掉末尾空行）
+fn banner_sections(content: &str, tokens: &[Token]) -> Vec<(u32, u32)> {
+    let banner_lines: Vec<u32> = tokens
+        .iter()
+        .filter(|t| {
+            t.kind == TokenKind::LineComment
+                && t.text[2..].trim_start().starts_with("====")
+                && content[..t.span.start]
+                    .rsplit('\n')
+                    .next()
+                    .is_some_and(|before| before.trim().is_empty())
+        })
+        .map(|t| offset_to_position(content, t.span.start).line)
+        .collect();
+
+    let lines: Vec<&str> = content.lines().collect();
+    // 相邻的分节注释行视为同一个标题
+    let headers: Vec<u32> = banner_lines
+        .iter()
+        .enumerate()
+        .filter(|&(i, &line)| i == 0 || banner_lines[i - 1] + 1 != line)
+        .map(|(_, &line)| line)
+        .collect();
+
+    headers
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let mut end = headers
+                .get(i + 1)
+                .map_or(lines.len() as u32, |&next| next)
+                .saturating_sub(1);
+            while end > start && lines[end as usize].trim().is_empty() {
+                end -= 1;
+            }
+            (start, end)
+        })
+        .collect()
+}
@@ -0,0 +1,1047 @@
+use std::ops::RangeInclusive;
+
+use tower_lsp::lsp_types::{FormattingOptions, Position, Range, TextEdit};
+
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::utils::offset_to_position;
+
+/// 超过该宽度的 `let` 行会把柯里化参数链拆成每行一个参数
+const MAX_LINE_WIDTH: usize = 100;
+
+/// 两侧各留一个空格的运算符
+const SPACED_OPERATORS: &[&str] = &[
+    "=", "=>", "->", "|->", "|>", "|", "&", "~", ":=", "==", "!=", "<=", ">=", "&&", "||",
+];
+
+/// 文档无法格式化的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    /// 该位置有无法识别的字符，或未闭合的字符串/注释
+    Unrecognized(Position),
+    /// 格式化结果的 token 序列与原文不一致（格式化器的缺陷）
+    TokensChanged,
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Unrecognized(position) => write!(
+                f,
+                "第 {} 行第 {} 列存在无法识别的字符或未闭合的字符串、注释",
+                position.line + 1,
+                position.character + 1
+            ),
+            FormatError::TokensChanged => write!(f, "格式化结果改变了 token 序列"),
+        }
+    }
+}
+
+/// 格式化整个文档，`indent_unit` 为一级缩进的文本
+///
+/// 存在无法识别的 token，或格式化前后的 token 序列不一致时返回错误，
+/// 此时不应修改文档。
+pub fn format_document(content: &str, indent_unit: &str) -> Result<String, FormatError> {
+    let tokens: Vec<Token> = Lexer::new(content).collect();
+    if let Some(unknown) = tokens.iter().find(|t| t.kind == TokenKind::Unknown) {
+        return Err(FormatError::Unrecognized(offset_to_position(
+            content,
+            unknown.span.start,
+        )));
+    }
+
+    let roles = analyze(&tokens);
+    let mut breaks = line_breaks(content, &tokens, &roles);
+    let mut lines = layout(&tokens, &roles, &breaks);
+    if wrap_curried_chains(content, &tokens, &lines, indent_unit, &mut breaks) {
+        lines = layout(&tokens, &roles, &breaks);
+    }
+
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut formatted = String::with_capacity(content.len());
+    for line in &lines {
+        if line.blank_before {
+            formatted.push_str(newline);
+        }
+        formatted.push_str(&render_line(content, &tokens, line, indent_unit));
+        formatted.push_str(newline);
+    }
+
+    if !same_tokens(content, &formatted) {
+        return Err(FormatError::TokensChanged);
+    }
+    Ok(formatted)
+}
+
+/// 根据编辑器的格式化选项得到一级缩进
+pub fn indent_unit(options: &FormattingOptions) -> String {
+    if options.insert_spaces {
+        " ".repeat(options.tab_size.max(1) as usize)
+    } else {
+        "\t".to_string()
+    }
+}
+
+/// 只格式化 `range` 覆盖的行：按行比较整篇格式化的结果，返回落在这些行内的修改
+///
+/// 整篇格式化失败时（例如存在未闭合的字符串）退回为按 token 重新缩进这些行。
+pub fn format_range(content: &str, range: Range, indent_unit: &str) -> Vec<TextEdit> {
+    let first = range.start.line as usize;
+    // 选区结束于行首时不包含该行
+    let last = match range.end {
+        end if end.character == 0 && end.line > range.start.line => end.line as usize - 1,
+        end => end.line as usize,
+    };
+    let Ok(formatted) = format_document(content, indent_unit) else {
+        return reindent_lines(content, first..=last, indent_unit);
+    };
+
+    let old: Vec<&str> = content.split_inclusive('\n').collect();
+    let new: Vec<&str> = formatted.split_inclusive('\n').collect();
+    let starts = line_starts(content);
+    let offset_of = |line: usize| starts.get(line).copied().unwrap_or(content.len());
+    hunks_within(&old, &new, first..=last)
+        .into_iter()
+        .map(|(old_lines, new_lines)| TextEdit {
+            range: Range::new(
+                offset_to_position(content, offset_of(old_lines.start)),
+                offset_to_position(content, offset_of(old_lines.end)),
+            ),
+            new_text: new[new_lines].concat(),
+        })
+        .collect()
+}
+
+/// 输入触发字符后的格式化
+///
+/// 换行时为新行设置缩进：紧跟在 `match v` 之后还会插入第一个分支的 `| `；
+/// 输入 `;` 或 `|` 时重新缩进当前行。只依赖 token，源码有语法错误时同样可用。
+pub fn format_on_type(
+    content: &str,
+    position: Position,
+    ch: &str,
+    indent_unit: &str,
+) -> Vec<TextEdit> {
+    let line = position.line as usize;
+    if ch != "\n" {
+        return reindent_lines(content, line..=line, indent_unit);
+    }
+
+    let Some(&line_start) = line_starts(content).get(line) else {
+        return Vec::new();
+    };
+    let rest = &content[line_start..];
+    let text_start = line_start + rest.len() - rest.trim_start_matches([' ', '\t']).len();
+    if !(text_start == content.len() || content[text_start..].starts_with(['\r', '\n'])) {
+        // 在行中间换行：光标后已有内容，按它重新缩进
+        return reindent_lines(content, line..=line, indent_unit);
+    }
+
+    let (level, first_arm) = probe_line(content, text_start, "|");
+    let new_text = if first_arm {
+        format!("{}| ", indent_unit.repeat(level))
+    } else {
+        indent_unit.repeat(probe_line(content, text_start, "_").0)
+    };
+    indent_edit(content, line_start, text_start, new_text)
+        .into_iter()
+        .collect()
+}
+
+/// token 在版面中的角色
+#[derive(Debug, Clone, Copy, Default)]
+struct Role {
+    /// 开始一个 match 分支的 `|`
+    arm: bool,
+    /// 结束一条语句的 `;`（位于块内或顶层）
+    statement_end: bool,
+    /// 导出链或解构块中分隔各项的 `&`
+    list_separator: bool,
+    /// `loop` 头部中 `loop` 之后直到 `;` 的 token
+    loop_header: bool,
+    /// 括号对应的另一半
+    partner: Option<usize>,
+}
+
+/// 一个输出行
+#[derive(Debug, Clone)]
+struct Line {
+    tokens: std::ops::Range<usize>,
+    indent: usize,
+    blank_before: bool,
+}
+
+fn is_opener(token: &Token) -> bool {
+    token.kind == TokenKind::Punct && matches!(token.text, "(" | "[" | "{")
+}
+
+fn is_closer(token: &Token) -> bool {
+    token.kind == TokenKind::Punct && matches!(token.text, ")" | "]" | "}")
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    token.kind == TokenKind::Keyword && token.text == keyword
+}
+
+fn analyze(tokens: &[Token]) -> Vec<Role> {
+    let mut roles = vec![Role::default(); tokens.len()];
+    // (开括号下标, 是否为 `let constraint { ... }` 解构块)
+    let mut brackets: Vec<(usize, bool)> = Vec::new();
+    // 尚未结束的 match 所在的括号深度
+    let mut matches: Vec<usize> = Vec::new();
+    let mut loop_depth: Option<usize> = None;
+    let mut top_level_amps = Vec::new();
+    let mut last_top_level_end = None;
+    let mut previous: [Option<&str>; 2] = [None, None];
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is_comment() {
+            continue;
+        }
+        let depth = brackets.len();
+        roles[i].loop_header = loop_depth.is_some();
+
+        if is_opener(token) {
+            let destructure = token.text == "{"
+                && matches!(
+                    previous,
+                    [Some("let"), _] | [Some("constraint"), Some("let")]
+                );
+            brackets.push((i, destructure));
+        } else if is_closer(token) {
+            if let Some((open, _)) = brackets.pop() {
+                roles[open].partner = Some(i);
+                roles[i].partner = Some(open);
+            }
+            let depth = brackets.len();
+            matches.retain(|&d| d <= depth);
+            if loop_depth.is_some_and(|d| d > depth) {
+                loop_depth = None;
+            }
+        } else if token.is_punct(";") {
+            matches.retain(|&d| d != depth);
+            if loop_depth == Some(depth) {
+                loop_depth = None;
+            }
+            roles[i].statement_end = brackets
+                .last()
+                .is_none_or(|&(open, _)| tokens[open].text == "{");
+            if depth == 0 {
+                last_top_level_end = Some(i);
+            }
+        } else if token.is_punct(",") {
+            matches.retain(|&d| d != depth);
+        } else if token.is_punct("|") {
+            roles[i].arm = matches.last() == Some(&depth);
+        } else if token.is_punct("&") {
+            match brackets.last() {
+                Some(&(_, destructure)) => roles[i].list_separator = destructure,
+                None => top_level_amps.push(i),
+            }
+        } else if is_keyword(token, "match") {
+            matches.push(depth);
+        } else if is_keyword(token, "loop") {
+            loop_depth = Some(depth);
+        }
+
+        previous = [Some(token.text), previous[0]];
+    }
+
+    // 文件末尾的导出链：最后一条顶层语句之后的顶层 `&`
+    for i in top_level_amps {
+        if last_top_level_end.is_none_or(|end| i > end) {
+            roles[i].list_separator = true;
+        }
+    }
+    roles
+}
+
+/// 每个 token 之前的换行数：0 表示同一行，1 表示换行，2 表示中间空一行
+fn line_breaks(content: &str, tokens: &[Token], roles: &[Role]) -> Vec<usize> {
+    let mut breaks: Vec<usize> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| match i {
+            0 => 0,
+            _ => content[tokens[i - 1].span.end..token.span.start]
+                .matches('\n')
+                .count()
+                .min(2),
+        })
+        .collect();
+
+    // `loop` 头部写在一行内
+    for i in 1..tokens.len() {
+        if roles[i].loop_header && tokens[i - 1].kind != TokenKind::LineComment {
+            breaks[i] = 0;
+        }
+    }
+
+    for (i, token) in tokens.iter().enumerate() {
+        let role = roles[i];
+        if token.is_punct("}") && role.partner.is_some_and(|open| open + 1 != i) {
+            breaks[i] = 1;
+        }
+        if role.arm {
+            breaks[i] = breaks[i].max(1);
+        }
+
+        let next = i + 1;
+        if next == tokens.len() {
+            break;
+        }
+        let trailing_comment = tokens[next].kind == TokenKind::LineComment && breaks[next] == 0;
+        if token.kind == TokenKind::LineComment {
+            breaks[next] = breaks[next].max(1);
+        } else if token.is_punct("{") && role.partner != Some(next) && !trailing_comment {
+            breaks[next] = 1;
+        } else if (role.statement_end || role.list_separator) && !trailing_comment {
+            breaks[next] = breaks[next].max(1);
+        }
+    }
+    breaks
+}
+
+fn layout(tokens: &[Token], roles: &[Role], breaks: &[usize]) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    for (i, &count) in breaks.iter().enumerate() {
+        match lines.last_mut() {
+            Some(line) if count == 0 => line.tokens.end = i + 1,
+            _ => lines.push(Line {
+                tokens: i..i + 1,
+                indent: 0,
+                blank_before: count > 1,
+            }),
+        }
+    }
+    assign_indents(tokens, roles, &mut lines);
+    lines
+}
+
+/// 括号内的缩进状态
+struct Scope {
+    /// 闭括号所在行的缩进
+    close: usize,
+    /// 括号内语句的缩进
+    content: usize,
+    /// 当前语句首行的缩进，续行在此基础上再缩进一级
+    head: usize,
+}
+
+/// 逐行计算缩进层级
+///
+/// 语句首行对齐所在括号的内容缩进；续行比语句首行多一级；
+/// match 分支比 `match` 所在行多一级，`then`/`else` 比 `if` 所在行多一级，
+/// 紧跟在 `then { ... }` 之后的 `else` 与闭括号对齐；
+/// 括号内容比开括号所在语句的首行多一级。只有注释的行与下一个代码行对齐。
+fn assign_indents(tokens: &[Token], roles: &[Role], lines: &mut [Line]) {
+    let mut scopes = vec![Scope {
+        close: 0,
+        content: 0,
+        head: 0,
+    }];
+    // (所在行的缩进, 所在作用域深度)
+    let mut matches: Vec<(usize, usize)> = Vec::new();
+    let mut ifs: Vec<(usize, usize)> = Vec::new();
+    let mut previous: Option<usize> = None;
+    // 上一个代码行的缩进
+    let mut previous_indent = 0;
+    let mut comment_lines = Vec::new();
+
+    for index in 0..lines.len() {
+        let range = lines[index].tokens.clone();
+        let Some(first) = range.clone().find(|&i| !tokens[i].is_comment()) else {
+            comment_lines.push(index);
+            continue;
+        };
+
+        let token = &tokens[first];
+        let depth = scopes.len();
+        let scope = &scopes[depth - 1];
+        let (indent, head) = if is_closer(token) {
+            (scope.close, false)
+        } else if roles[first].arm {
+            let indent = matches
+                .last()
+                .map_or(scope.content, |&(indent, _)| indent + 1);
+            (indent, true)
+        } else if is_keyword(token, "then") || is_keyword(token, "else") {
+            // `then { ... }` 之后的 `else` 与闭括号对齐：`if c then {` 时与 `if` 同级，
+            // `then {` 单独成行时与 `then` 同级
+            let after_block = previous.is_some_and(|i| tokens[i].is_punct("}"));
+            let indent = match ifs.last() {
+                Some(&(_, d)) if d == depth && after_block => previous_indent,
+                Some(&(indent, d)) if d == depth => indent + 1,
+                _ => scope.head + 1,
+            };
+            (indent, true)
+        } else if starts_statement(tokens, roles, previous) {
+            (scope.content, true)
+        } else {
+            (scope.head + 1, false)
+        };
+        if head {
+            scopes[depth - 1].head = indent;
+        }
+
+        let comment_indent = if is_closer(token) { indent + 1 } else { indent };
+        for line in comment_lines.drain(..) {
+            lines[line].indent = comment_indent;
+        }
+        lines[index].indent = indent;
+        previous_indent = indent;
+
+        for i in range.filter(|&i| !tokens[i].is_comment()) {
+            let token = &tokens[i];
+            let depth = scopes.len();
+            if is_opener(token) {
+                let head = scopes[depth - 1].head;
+                scopes.push(Scope {
+                    close: head,
+                    content: head + 1,
+                    head: head + 1,
+                });
+            } else if is_closer(token) {
+                if depth > 1 {
+                    scopes.pop();
+                }
+                let depth = scopes.len();
+                matches.retain(|&(_, d)| d <= depth);
+                ifs.retain(|&(_, d)| d <= depth);
+            } else if token.is_punct(";") || token.is_punct(",") {
+                matches.retain(|&(_, d)| d != depth);
+                ifs.retain(|&(_, d)| d != depth);
+            } else if is_keyword(token, "match") {
+                matches.push((indent, depth));
+            } else if is_keyword(token, "if") {
+                ifs.push((indent, depth));
+            } else if is_keyword(token, "else") && ifs.last().is_some_and(|&(_, d)| d == depth) {
+                ifs.pop();
+            }
+            previous = Some(i);
+        }
+    }
+}
+
+/// 上一个代码 token 之后是否开始一条新语句（或列表中的新一项）
+fn starts_statement(tokens: &[Token], roles: &[Role], previous: Option<usize>) -> bool {
+    let Some(previous) = previous else {
+        return true;
+    };
+    let token = &tokens[previous];
+    token.kind == TokenKind::Punct
+        && (matches!(token.text, "(" | "[" | "{" | "}" | ";" | "," | "&")
+            || (token.text == "|" && !roles[previous].arm))
+}
+
+fn render_line(content: &str, tokens: &[Token], line: &Line, indent_unit: &str) -> String {
+    let mut text = indent_unit.repeat(line.indent);
+    for i in line.tokens.clone() {
+        if i > line.tokens.start {
+            let (prev, next) = (&tokens[i - 1], &tokens[i]);
+            text.push_str(spacing(
+                prev,
+                next,
+                &content[prev.span.end..next.span.start],
+            ));
+        }
+        text.push_str(match tokens[i].kind {
+            TokenKind::LineComment => tokens[i].text.trim_end(),
+            _ => tokens[i].text,
+        });
+    }
+    text.truncate(text.trim_end().len());
+    text
+}
+
+/// 同一行内相邻两个 token 之间的空白，`gap` 为原文中的空白
+fn spacing(prev: &Token, next: &Token, gap: &str) -> &'static str {
+    let is = |token: &Token, puncts: &[&str]| {
+        token.kind == TokenKind::Punct && puncts.contains(&token.text)
+    };
+    if next.kind == TokenKind::LineComment {
+        "  "
+    } else if is(prev, &["(", "[", "::"]) || is(next, &[")", "]", ",", ";", "::", ":"]) {
+        ""
+    } else if is(prev, &[",", ":"]) || is(prev, SPACED_OPERATORS) || is(next, SPACED_OPERATORS) {
+        " "
+    } else if is(prev, &["{"]) && is(next, &["}"]) {
+        ""
+    } else if is(prev, &["{", "}"]) || is(next, &["{", "}"]) {
+        " "
+    } else if gap.is_empty() {
+        ""
+    } else {
+        " "
+    }
+}
+
+/// 把过长的 `let` 行中的柯里化参数链拆成每行一个 `constraint x: T =>`，返回是否有改动
+fn wrap_curried_chains(
+    content: &str,
+    tokens: &[Token],
+    lines: &[Line],
+    indent_unit: &str,
+    breaks: &mut [usize],
+) -> bool {
+    let mut changed = false;
+    for line in lines {
+        if !is_keyword(&tokens[line.tokens.start], "let")
+            || render_line(content, tokens, line, indent_unit)
+                .chars()
+                .count()
+                <= MAX_LINE_WIDTH
+        {
+            continue;
+        }
+
+        let mut depth = 0usize;
+        let mut params = Vec::new();
+        for i in line.tokens.clone() {
+            let token = &tokens[i];
+            if is_opener(token) {
+                depth += 1;
+            } else if is_closer(token) {
+                depth = depth.saturating_sub(1);
+            } else if depth == 0
+                && is_keyword(token, "constraint")
+                && (tokens[i - 1].is_punct("=") || tokens[i - 1].is_punct("=>"))
+            {
+                params.push(i);
+            }
+        }
+        if params.len() < 2 {
+            continue;
+        }
+        for i in params {
+            breaks[i] = 1;
+        }
+        changed = true;
+    }
+    changed
+}
+
+/// 按原文的换行计算每一行的缩进，不要求源码能通过解析
+fn source_lines(content: &str, tokens: &[Token], roles: &[Role]) -> Vec<Line> {
+    let breaks: Vec<usize> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| match i {
+            0 => 0,
+            _ => content[tokens[i - 1].span.end..token.span.start]
+                .matches('\n')
+                .count(),
+        })
+        .collect();
+    layout(tokens, roles, &breaks)
+}
+
+/// 按 token 重新缩进 `lines` 中的行（行号从 0 开始），只修改行首空白
+fn reindent_lines(content: &str, lines: RangeInclusive<usize>, indent_unit: &str) -> Vec<TextEdit> {
+    let tokens: Vec<Token> = Lexer::new(content).collect();
+    let roles = analyze(&tokens);
+    let starts = line_starts(content);
+    source_lines(content, &tokens, &roles)
+        .iter()
+        .filter_map(|line| {
+            let text_start = tokens[line.tokens.start].span.start;
+            let number = starts.partition_point(|&start| start <= text_start) - 1;
+            if !lines.contains(&number) {
+                return None;
+            }
+            indent_edit(
+                content,
+                starts[number],
+                text_start,
+                indent_unit.repeat(line.indent),
+            )
+        })
+        .collect()
+}
+
+/// 在 `offset` 处临时插入 `probe` 并计算它所在行的缩进层级，
+/// 同时返回它是否会是某个 `match` 的第一个分支
+fn probe_line(content: &str, offset: usize, probe: &str) -> (usize, bool) {
+    let probed = format!("{}{probe}{}", &content[..offset], &content[offset..]);
+    let tokens: Vec<Token> = Lexer::new(&probed).collect();
+    let Some(index) = tokens.iter().position(|t| t.span.start == offset) else {
+        return (0, false);
+    };
+    let roles = analyze(&tokens);
+    let level = source_lines(&probed, &tokens, &roles)
+        .iter()
+        .find(|line| line.tokens.contains(&index))
+        .map_or(0, |line| line.indent);
+    (
+        level,
+        roles[index].arm && is_first_arm(&tokens, &roles, index),
+    )
+}
+
+/// 分支 `|` 与它所属的 `match` 之间是否没有其他分支
+fn is_first_arm(tokens: &[Token], roles: &[Role], arm: usize) -> bool {
+    let mut depth = 0usize;
+    for i in (0..arm).rev() {
+        let token = &tokens[i];
+        if token.is_comment() {
+            continue;
+        }
+        if is_closer(token) {
+            depth += 1;
+        } else if is_opener(token) {
+            if depth == 0 {
+                return false;
+            }
+            depth -= 1;
+        } else if depth == 0 {
+            if roles[i].arm || token.is_punct(";") {
+                return false;
+            }
+            if is_keyword(token, "match") {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// 把 `line_start..text_start` 的行首空白替换为 `new_text`，没有变化时返回 None
+fn indent_edit(
+    content: &str,
+    line_start: usize,
+    text_start: usize,
+    new_text: String,
+) -> Option<TextEdit> {
+    (content[line_start..text_start] != new_text).then(|| TextEdit {
+        range: Range::new(
+            offset_to_position(content, line_start),
+            offset_to_position(content, text_start),
+        ),
+        new_text,
+    })
+}
+
+/// 每一行起始处的字节偏移
+fn line_starts(content: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// 按行比较，返回 (原文行范围, 新文本行范围) 形式的修改块
+///
+/// 使用 Myers 差分的线性空间版本：每次找出最短编辑路径中间的一段公共行，再对两侧递归。
+fn line_hunks(old: &[&str], new: &[&str]) -> Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    let mut matched = Vec::new();
+    common_lines(old, new, 0, 0, &mut matched);
+
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (mi, mj) in matched
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        if mi > i || mj > j {
+            hunks.push((i..mi, j..mj));
+        }
+        (i, j) = (mi + 1, mj + 1);
+    }
+    hunks
+}
+
+/// 只保留落在 `lines`（原文行号）内的修改块
+///
+/// 跨出这些行的修改块按去掉首尾空白后的内容重新对齐：只改了缩进的行一一对应，可以逐行保留；
+/// 其余无法对齐的修改只在完全落在 `lines` 内时保留。
+fn hunks_within(
+    old: &[&str],
+    new: &[&str],
+    lines: RangeInclusive<usize>,
+) -> Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    let (first, last) = (*lines.start(), *lines.end());
+    let inside = |old_lines: &std::ops::Range<usize>| {
+        old_lines.start >= first && old_lines.start <= last && old_lines.end <= last + 1
+    };
+
+    let mut kept = Vec::new();
+    for (old_lines, new_lines) in line_hunks(old, new) {
+        if inside(&old_lines) {
+            kept.push((old_lines, new_lines));
+            continue;
+        }
+        if old_lines.start > last || old_lines.end <= first {
+            continue;
+        }
+
+        let old_trimmed: Vec<&str> = old[old_lines.clone()].iter().map(|l| l.trim()).collect();
+        let new_trimmed: Vec<&str> = new[new_lines.clone()].iter().map(|l| l.trim()).collect();
+        let end = (
+            old_trimmed.len()..old_trimmed.len(),
+            new_trimmed.len()..new_trimmed.len(),
+        );
+        let (mut i, mut j) = (0, 0);
+        for (sub_old, sub_new) in line_hunks(&old_trimmed, &new_trimmed)
+            .into_iter()
+            .chain(std::iter::once(end))
+        {
+            // 两个子块之间的行内容相同，只有缩进不同
+            for k in 0..sub_old.start - i {
+                let (o, n) = (old_lines.start + i + k, new_lines.start + j + k);
+                if old[o] != new[n] && inside(&(o..o + 1)) {
+                    kept.push((o..o + 1, n..n + 1));
+                }
+            }
+            let sub_old_lines = old_lines.start + sub_old.start..old_lines.start + sub_old.end;
+            let sub_new_lines = new_lines.start + sub_new.start..new_lines.start + sub_new.end;
+            if (!sub_old.is_empty() || !sub_new.is_empty()) && inside(&sub_old_lines) {
+                kept.push((sub_old_lines, sub_new_lines));
+            }
+            (i, j) = (sub_old.end, sub_new.end);
+        }
+    }
+    kept
+}
+
+/// 按顺序收集 `a` 与 `b` 的一个最长公共子序列中各行的 (原文行号, 新文本行号)
+fn common_lines(
+    a: &[&str],
+    b: &[&str],
+    a_start: usize,
+    b_start: usize,
+    out: &mut Vec<(usize, usize)>,
+) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    out.extend((0..prefix).map(|k| (a_start + k, b_start + k)));
+
+    let (mid_a, mid_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    if !mid_a.is_empty() && !mid_b.is_empty() {
+        // 去掉公共前后缀后两侧都非空，编辑距离至少为 2，两半的编辑距离都严格更小
+        if let Some((x, y, end_x, end_y)) = middle_snake(mid_a, mid_b) {
+            let (a_mid, b_mid) = (a_start + prefix, b_start + prefix);
+            common_lines(&mid_a[..x], &mid_b[..y], a_mid, b_mid, out);
+            out.extend((0..end_x - x).map(|k| (a_mid + x + k, b_mid + y + k)));
+            common_lines(
+                &mid_a[end_x..],
+                &mid_b[end_y..],
+                a_mid + end_x,
+                b_mid + end_y,
+                out,
+            );
+        }
+    }
+
+    let (a_end, b_end) = (a_start + a.len() - suffix, b_start + b.len() - suffix);
+    out.extend((0..suffix).map(|k| (a_end + k, b_end + k)));
+}
+
+/// 同时从两端搜索最短编辑路径，返回两个方向相遇处的公共段 (x, y, end_x, end_y)
+fn middle_snake(a: &[&str], b: &[&str]) -> Option<(usize, usize, usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2 + 1;
+    let index = |k: isize| (k + max) as usize;
+    // forward[k]：从起点出发在对角线 k 上能到达的最远 x；backward 为从终点反向搜索的结果
+    let mut forward = vec![0isize; 2 * max as usize + 1];
+    let mut backward = vec![0isize; 2 * max as usize + 1];
+
+    for d in 0..max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let (start_x, start_y) = (x, x - k);
+            let mut y = start_y;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let reverse_k = delta - k;
+            if delta % 2 != 0
+                && (-(d - 1)..=d - 1).contains(&reverse_k)
+                && x + backward[index(reverse_k)] >= n
+            {
+                return Some((start_x as usize, start_y as usize, x as usize, y as usize));
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let (start_x, start_y) = (x, x - k);
+            let mut y = start_y;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            let forward_k = delta - k;
+            if delta % 2 == 0 && (-d..=d).contains(&forward_k) && x + forward[index(forward_k)] >= n
+            {
+                return Some((
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - start_x) as usize,
+                    (m - start_y) as usize,
+                ));
+            }
+        }
+    }
+    // 两个方向最迟在 d = ⌈(n + m) / 2⌉ 时相遇，不会到达这里
+    None
+}
+
+/// 格式化只能改变空白：比较前后的 token 序列（行注释忽略行尾空白）
+fn same_tokens(before: &str, after: &str) -> bool {
+    fn key<'a>(token: Token<'a>) -> (TokenKind, &'a str) {
+        match token.kind {
+            TokenKind::LineComment => (token.kind, token.text.trim_end()),
+            _ => (token.kind, token.text),
+        }
+    }
+    Lexer::new(before).map(key).eq(Lexer::new(after).map(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLES: &[&str] = &[
+        include_str!("../../examples/lib/any.mu"),
+        include_str!("../../examples/lib/combinator.mu"),
+        include_str!("../../examples/lib/controlflow.mu"),
+        include_str!("../../examples/lib/int.mu"),
+        include_str!("../../examples/lib/list.mu"),
+        include_str!("../../examples/lib/maybe.mu"),
+        include_str!("../../examples/lib/panic.mu"),
+        include_str!("../../examples/lib/rbtree.mu"),
+        include_str!("../../examples/lib/sort.mu"),
+        include_str!("../../examples/lib/string.mu"),
+    ];
+
+    fn format(content: &str) -> String {
+        format_document(content, "    ").expect("document should format")
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for example in EXAMPLES {
+            let once = format(example);
+            assert_eq!(format(&once), once);
+        }
+    }
+
+    #[test]
+    fn formatting_preserves_tokens() {
+        for example in EXAMPLES {
+            assert!(same_tokens(example, &format(example)));
+        }
+    }
+
+    #[test]
+    fn else_after_then_block_aligns_with_closing_brace() {
+        let source = "\
+let constraint go: any = dyn_rec go: match
+    | constraint (h: any ~ t: any) => if pred(h)
+        then {
+            let constraint Just::(v: any) = maybe_pkg;
+            v(h)
+        }
+        else go(t)
+    | panic;
+let constraint f: any = constraint n: nat => {
+    if n > 0 then {
+        n
+    }
+    else 0
+};
+";
+        assert_eq!(format(source), source);
+    }
+
+    #[test]
+    fn comments_keep_their_lines() {
+        let source = "\
+// leading comment
+let constraint a: any = 1;  // trailing comment
+let constraint f: any = match
+        // arm comment
+    | 0 => a
+    | panic;
+";
+        let expected = "\
+// leading comment
+let constraint a: any = 1;  // trailing comment
+let constraint f: any = match
+    // arm comment
+    | 0 => a
+    | panic;
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn unclosed_string_is_not_formatted() {
+        assert_eq!(
+            format_document("let a = 1;\nlet b = \"oops;\n", "    "),
+            Err(FormatError::Unrecognized(Position::new(1, 8)))
+        );
+    }
+
+    #[test]
+    fn line_hunks_find_a_longest_common_subsequence() {
+        // 线性同余生成器，得到可复现的伪随机行序列
+        let mut seed = 7u64;
+        let mut next = |limit: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % limit
+        };
+        let lines = ["a", "b", "c", "d", "e"];
+
+        for _ in 0..300 {
+            let old: Vec<&str> = (0..next(12)).map(|_| lines[next(5) as usize]).collect();
+            let new: Vec<&str> = (0..next(12)).map(|_| lines[next(5) as usize]).collect();
+            let hunks = line_hunks(&old, &new);
+
+            // 应用修改块后得到新文本，未修改的行数等于最长公共子序列的长度
+            let mut rebuilt = Vec::new();
+            let mut kept = 0;
+            let mut cursor = 0;
+            for (old_lines, new_lines) in &hunks {
+                kept += old_lines.start - cursor;
+                rebuilt.extend_from_slice(&old[cursor..old_lines.start]);
+                rebuilt.extend_from_slice(&new[new_lines.clone()]);
+                cursor = old_lines.end;
+            }
+            kept += old.len() - cursor;
+            rebuilt.extend_from_slice(&old[cursor..]);
+            assert_eq!(rebuilt, new);
+
+            let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    lcs[i][j] = if old[i] == new[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        lcs[i + 1][j].max(lcs[i][j + 1])
+                    };
+                }
+            }
+            assert_eq!(kept, lcs[0][0], "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn range_formatting_only_touches_selected_lines() {
+        let source = "\
+let constraint a: any = {
+  1
+};
+let constraint b: any = {
+      2
+};
+";
+        let range = Range::new(Position::new(4, 0), Position::new(4, 3));
+        let edits = format_range(source, range, "    ");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].range.start.line, 4);
+        assert_eq!(edits[0].new_text, "    2\n");
+    }
+
+    #[test]
+    fn range_formatting_leaves_adjacent_lines_alone() {
+        // 前后相邻的行同样缩进错误，整篇格式化时它们与选中的行属于同一个修改块
+        let source = "\
+let constraint b: any = {
+      1,
+      2,
+      3
+};
+";
+        let range = Range::new(Position::new(2, 0), Position::new(3, 0));
+        let edits = format_range(source, range, "    ");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(2, 0), Position::new(3, 0))
+        );
+        assert_eq!(edits[0].new_text, "    2,\n");
+    }
+
+    /// 在 `(line, character)` 处输入 `ch` 后的格式化结果：(修改范围, 新文本)
+    fn on_type(source: &str, line: u32, character: u32, ch: &str) -> Vec<(Range, String)> {
+        format_on_type(source, Position::new(line, character), ch, "    ")
+            .into_iter()
+            .map(|edit| (edit.range, edit.new_text))
+            .collect()
+    }
+
+    fn insert_at(line: u32, text: &str) -> Vec<(Range, String)> {
+        let start = Position::new(line, 0);
+        vec![(Range::new(start, start), text.to_string())]
+    }
+
+    #[test]
+    fn newline_after_match_inserts_the_first_arm() {
+        let source = "let constraint f: any = match v\n";
+        assert_eq!(on_type(source, 1, 0, "\n"), insert_at(1, "    | "));
+
+        // match 位于块内时分支比 match 所在行多一级
+        let source = "let constraint f: any = constraint x: any => {\n    match x\n";
+        assert_eq!(on_type(source, 2, 0, "\n"), insert_at(2, "        | "));
+    }
+
+    #[test]
+    fn newline_after_an_opening_brace_indents_one_level() {
+        let source = "let constraint f: any = constraint x: any => {\n";
+        assert_eq!(on_type(source, 1, 0, "\n"), insert_at(1, "    "));
+
+        // 缩进已经正确时不修改
+        let source = "let constraint f: any = constraint x: any => {\n    ";
+        assert!(on_type(source, 1, 4, "\n").is_empty());
+    }
+
+    #[test]
+    fn semicolon_and_bar_reindent_the_current_line() {
+        let source = "let constraint f: any = {\nlet x = 1;\n};\n";
+        assert_eq!(on_type(source, 1, 10, ";"), insert_at(1, "    "));
+
+        let source = "let constraint f: any = match v\n    | 0 => 1\n|";
+        assert_eq!(on_type(source, 2, 1, "|"), insert_at(2, "    "));
+    }
+
+    #[test]
+    fn on_type_formatting_works_on_documents_that_do_not_parse() {
+        // `let x = ;` 缺少表达式，缩进只依赖 token
+        let source = "let constraint f: any = {\n    let x = ;\n";
+        assert_eq!(on_type(source, 2, 0, "\n"), insert_at(2, "    "));
+    }
+
+    #[test]
+    fn range_formatting_falls_back_to_token_indentation() {
+        // 未闭合的字符串使整篇格式化失败，选中的行改为按 token 重新缩进
+        let source = "let constraint f: any = {\nlet x = 1;\nlet s = \"oops\n";
+        let range = Range::new(Position::new(1, 0), Position::new(1, 10));
+        let edits: Vec<(Range, String)> = format_range(source, range, "    ")
+            .into_iter()
+            .map(|edit| (edit.range, edit.new_text))
+            .collect();
+        assert_eq!(edits, insert_at(1, "    "));
+    }
+}
@@ -0,0 +1,74 @@
+use tower_lsp::lsp_types::{
+    DocumentHighlight, DocumentHighlightKind, Location, Position, Range, Url,
+};
+
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::utils::{position_in_range, position_to_offset, ranges_equal};
+
+/// 高亮光标所在符号在当前文件中的所有出现位置
+///
+/// 按 `collect_references` 解析出的定义归类，因此同名的遮蔽变量不会混在一起。
+/// 定义处以及 `x := ...`、`__assign!(x, ...)` 中被赋值的变量标记为 WRITE，其余为 READ。
+pub fn document_highlights(
+    content: &str,
+    uri: &Url,
+    references: &[(Range, Location)],
+    position: Position,
+) -> Option<Vec<DocumentHighlight>> {
+    let target = references
+        .iter()
+        .find(|(use_range, _)| position_in_range(&position, use_range))
+        .or_else(|| {
+            references
+                .iter()
+                .find(|(_, def)| def.uri == *uri && position_in_range(&position, &def.range))
+        })
+        .map(|(_, def)| def)?;
+
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let mut highlights: Vec<DocumentHighlight> = references
+        .iter()
+        .filter(|(_, def)| def.uri == target.uri && ranges_equal(&def.range, &target.range))
+        .map(|(use_range, _)| DocumentHighlight {
+            range: *use_range,
+            kind: Some(if assignment_target_at(content, &tokens, use_range) {
+                DocumentHighlightKind::WRITE
+            } else {
+                DocumentHighlightKind::READ
+            }),
+        })
+        .collect();
+
+    if target.uri == *uri
+        && !highlights
+            .iter()
+            .any(|h| ranges_equal(&h.range, &target.range))
+    {
+        highlights.push(DocumentHighlight {
+            range: target.range,
+            kind: Some(DocumentHighlightKind::WRITE),
+        });
+    }
+
+    highlights.sort_by_key(|h| (h.range.start.line, h.range.start.character));
+    Some(highlights)
+}
+
+fn assignment_target_at(content: &str, tokens: &[Token], range: &Range) -> bool {
+    position_to_offset(content, range.start)
+        .and_then(|offset| tokens.iter().position(|t| t.span.start == offset))
+        .is_some_and(|i| is_assignment_target(tokens, i))
+}
+
+/// 第 `i` 个 token 是否为 `x := value` 的左侧或 `__assign!(x, value)` 的第一个参数
+///
+/// `tokens` 中不应包含注释。
+pub fn is_assignment_target(tokens: &[Token], i: usize) -> bool {
+    let followed_by_assign = tokens.get(i + 1).is_some_and(|t| t.is_punct(":="));
+    let assign_macro_argument = i >= 3
+        && tokens[i - 1].is_punct("(")
+        && tokens[i - 2].is_punct("!")
+        && tokens[i - 3].kind == TokenKind::Ident
+        && tokens[i - 3].text == "__assign";
+    followed_by_assign || assign_macro_argument
+}
@@ -0,0 +1,265 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use mutica::{
+    mutica_compiler::parser::{
+        WithLocation,
+        ast::{FlowedMetaData, LinearTypeAst},
+    },
+    mutica_core::util::source_info::SourceFile,
+};
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::lsp::ast_processor::child_nodes;
+use crate::lsp::utils::{offset_to_position, position_in_range};
+
+type FlowedNode = WithLocation<LinearTypeAst, FlowedMetaData>;
+
+/// 声明约束最多渲染的节点数，避免 `rec` 等节点把整个函数体显示出来
+const MAX_CONSTRAINT_NODES: usize = 48;
+
+/// 绑定处 (文件路径, 起始偏移, 结束偏移) -> 渲染后的声明约束
+pub type DeclaredConstraints = HashMap<(String, usize, usize), String>;
+
+/// 遍历分析后的语法树（包括导入模块），记录每个 `Bind` 节点绑定的变量及其约束
+pub fn collect_declared_constraints(root: &FlowedNode, constraints: &mut DeclaredConstraints) {
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        if let LinearTypeAst::Bind { var, expr } = node.value()
+            && let Some(loc) = var.location()
+        {
+            let span = loc.span();
+            constraints.insert(
+                (loc.source().filepath(), span.start, span.end),
+                render_constraint(expr),
+            );
+        }
+        pending.extend(child_nodes(node));
+    }
+}
+
+/// 根据节点的 FlowedMetaData 生成悬停信息
+/// 同时登记使用处和（位于当前文件中的）定义处，返回值为 (range, markdown)
+///
+/// `seen` 记录已登记的字节范围，同一范围只登记一次。
+pub fn collect_hover_entry(
+    node: &FlowedNode,
+    table: &mut Vec<(Range, String)>,
+    seen: &mut HashSet<(usize, usize)>,
+    source_file: &SourceFile,
+    constraints: &DeclaredConstraints,
+) {
+    let Some(use_loc) = node.location() else {
+        return;
+    };
+    if use_loc.source() != source_file {
+        return;
+    }
+    let Some(def_loc) = node.payload().reference().and_then(|r| r.location()) else {
+        return;
+    };
+
+    let content = source_file.content();
+    let use_span = use_loc.span();
+    let def_span = def_loc.span();
+    let constraint = constraints
+        .get(&(def_loc.source().filepath(), def_span.start, def_span.end))
+        .map(String::as_str);
+    let markdown = render_definition(
+        def_loc.source(),
+        def_span.start,
+        def_span.end,
+        constraint,
+        source_file,
+    );
+
+    if seen.insert((use_span.start, use_span.end)) {
+        table.push((
+            Range {
+                start: offset_to_position(content, use_span.start),
+                end: offset_to_position(content, use_span.end),
+            },
+            markdown.clone(),
+        ));
+    }
+
+    if def_loc.source() == source_file && seen.insert((def_span.start, def_span.end)) {
+        let def_range = Range {
+            start: offset_to_position(content, def_span.start),
+            end: offset_to_position(content, def_span.end),
+        };
+        table.push((def_range, markdown));
+    }
+}
+
+/// 查找包含光标位置的最内层悬停条目
+pub fn find_hover<'a>(
+    table: &'a [(Range, String)],
+    position: &Position,
+) -> Option<&'a (Range, String)> {
+    table
+        .iter()
+        .filter(|(range, _)| position_in_range(position, range))
+        .max_by_key(|(range, _)| {
+            (
+                range.start.line,
+                range.start.character,
+                std::cmp::Reverse((range.end.line, range.end.character)),
+            )
+        })
+}
+
+/// 将定义处渲染为 Markdown：声明约束、定义位置以及来源模块
+fn render_definition(
+    def_source: &SourceFile,
+    def_start: usize,
+    def_end: usize,
+    constraint: Option<&str>,
+    current: &SourceFile,
+) -> String {
+    let content = def_source.content();
+    let name = content.get(def_start..def_end).unwrap_or("<unknown>");
+    let signature = match constraint {
+        Some(constraint) => format!("{}: {}", name, constraint),
+        None => name.to_string(),
+    };
+
+    let position = offset_to_position(content, def_start);
+    let filepath = def_source.filepath();
+    let module = Path::new(&filepath)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| filepath.clone());
+    let origin = if def_source == current {
+        format!("`{}` (current file)", module)
+    } else {
+        format!("`{}` (imported from `{}`)", module, filepath)
+    };
+
+    format!(
+        "```mutica\n{}\n```\n\n---\n\n**Defined at** line {}, column {}\n\n**Module** {}",
+        signature,
+        position.line + 1,
+        position.character + 1,
+        origin
+    )
+}
+
+/// 将约束节点渲染为源码形式的文本，超过 MAX_CONSTRAINT_NODES 个节点的部分显示为 `…`
+pub fn render_constraint(node: &FlowedNode) -> String {
+    let mut budget = MAX_CONSTRAINT_NODES;
+    let mut out = String::new();
+    render_node(node, &mut budget, &mut out);
+    out
+}
+
+fn render_node(node: &FlowedNode, budget: &mut usize, out: &mut String) {
+    if *budget == 0 {
+        out.push('…');
+        return;
+    }
+    *budget -= 1;
+
+    let list = |items: &mut dyn Iterator<Item = &FlowedNode>,
+                separator: &str,
+                budget: &mut usize,
+                out: &mut String| {
+        for (i, item) in items.enumerate() {
+            if i > 0 {
+                out.push_str(separator);
+            }
+            render_operand(item, budget, out);
+        }
+    };
+
+    match node.value() {
+        LinearTypeAst::AllOf(items) => list(&mut items.iter(), " & ", budget, out),
+        LinearTypeAst::AnyOf(items) => list(&mut items.iter(), " | ", budget, out),
+        LinearTypeAst::Tuple(items) => {
+            out.push('(');
+            list(&mut items.iter().map(|(item, _)| item), ", ", budget, out);
+            if items.len() == 1 {
+                out.push(',');
+            }
+            out.push(')');
+        }
+        LinearTypeAst::Cons { head, tail } | LinearTypeAst::List { head, tail } => {
+            out.push('[');
+            list(&mut head.iter().map(|(item, _)| item), ", ", budget, out);
+            if !head.is_empty() {
+                out.push_str(", ");
+            }
+            out.push_str("..");
+            render_operand(tail, budget, out);
+            out.push(']');
+        }
+        LinearTypeAst::Match { .. } => out.push_str("match …"),
+        LinearTypeAst::Generic {
+            expr, constraint, ..
+        } => {
+            render_node(expr, budget, out);
+            for (i, (name, c)) in constraint.iter().enumerate() {
+                out.push_str(if i == 0 { " where " } else { ", " });
+                out.push_str(name);
+                out.push_str(": ");
+                render_operand(c, budget, out);
+            }
+        }
+        LinearTypeAst::Invoke { func, arg, .. } => {
+            render_operand(func, budget, out);
+            out.push('(');
+            render_node(arg, budget, out);
+            out.push(')');
+        }
+        LinearTypeAst::Namespace { tag, expr } => {
+            out.push_str(tag.value());
+            out.push_str("::");
+            render_operand(expr, budget, out);
+        }
+        LinearTypeAst::Bind { var, expr } => {
+            out.push_str(var.value());
+            out.push_str(": ");
+            render_node(expr, budget, out);
+        }
+        LinearTypeAst::StaticFixPoint { var, expr } => {
+            out.push_str("rec ");
+            out.push_str(var.value());
+            out.push_str(": ");
+            render_node(expr, budget, out);
+        }
+        LinearTypeAst::Lazy(inner) | LinearTypeAst::Range { ty: inner, .. } => {
+            render_node(inner, budget, out)
+        }
+        LinearTypeAst::SubOf { value } => {
+            out.push_str("sub ");
+            render_operand(value, budget, out);
+        }
+        LinearTypeAst::Mutable { value } => {
+            out.push_str("mut ");
+            render_operand(value, budget, out);
+        }
+        LinearTypeAst::Char => out.push_str("char"),
+        LinearTypeAst::Float => out.push_str("float"),
+        LinearTypeAst::NaturalNumberSet => out.push_str("nat"),
+        LinearTypeAst::FloatLiteral(value) => out.push_str(&value.to_string()),
+        LinearTypeAst::CharLiteral(value) => out.push_str(&format!("{:?}", value)),
+        LinearTypeAst::NaturalNumberLiteral(value) => out.push_str(&value.to_string()),
+        LinearTypeAst::Variable(name) => out.push_str(name),
+        LinearTypeAst::AtomicOpcode(opcode) => out.push_str(&format!("__opcode!({})", opcode)),
+    }
+}
+
+/// 作为其他结构的一部分渲染，并集、交集和绑定需要加括号
+fn render_operand(node: &FlowedNode, budget: &mut usize, out: &mut String) {
+    let compound = matches!(
+        node.value(),
+        LinearTypeAst::AllOf(items) | LinearTypeAst::AnyOf(items) if items.len() > 1
+    ) || matches!(node.value(), LinearTypeAst::Bind { .. });
+    if compound {
+        out.push('(');
+        render_node(node, budget, out);
+        out.push(')');
+    } else {
+        render_node(node, budget, out);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range as ByteRange;
+use std::path::{Path, PathBuf};
+
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::symbols::statement_end;
+
+/// 源码中的一个 `import "..."` 表达式
+#[derive(Debug, Clone)]
+pub struct ImportSpecifier {
+    /// 引号内的路径文本
+    pub path: String,
+    /// 从 `import` 关键字到字符串结束引号的字节范围
+    pub span: ByteRange<usize>,
+}
+
+impl ImportSpecifier {
+    /// 引号内路径文本的字节范围
+    pub fn path_span(&self) -> ByteRange<usize> {
+        self.span.end - 1 - self.path.len()..self.span.end - 1
+    }
+}
+
+/// 用词法分析找出所有 `import "..."`，注释和其他字符串中的内容不会被误认
+pub fn import_specifiers(content: &str) -> Vec<ImportSpecifier> {
+    let tokens: Vec<_> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    tokens
+        .windows(2)
+        .filter_map(|pair| {
+            let (keyword, string) = (&pair[0], &pair[1]);
+            if keyword.kind != TokenKind::Keyword
+                || keyword.text != "import"
+                || string.kind != TokenKind::String
+            {
+                return None;
+            }
+            Some(ImportSpecifier {
+                path: string.text[1..string.text.len() - 1].to_string(),
+                span: keyword.span.start..string.span.end,
+            })
+        })
+        .collect()
+}
+
+/// 顶层的 `let [constraint] alias[: T] = import "...";`，返回 alias 到导入路径的映射
+pub fn module_aliases<'a>(tokens: &[Token<'a>]) -> HashMap<&'a str, &'a str> {
+    let mut aliases = HashMap::new();
+    for i in 0..tokens.len() {
+        if tokens[i].text != "let" {
+            continue;
+        }
+        let mut name = i + 1;
+        if tokens.get(name).is_some_and(|t| t.text == "constraint") {
+            name += 1;
+        }
+        let Some(alias) = tokens.get(name).filter(|t| t.kind == TokenKind::Ident) else {
+            continue;
+        };
+        let end = statement_end(tokens, name, false);
+        let statement = &tokens[name..end];
+        if let [.., keyword, string, semicolon] = statement
+            && keyword.text == "import"
+            && string.kind == TokenKind::String
+            && semicolon.is_punct(";")
+            && statement.len() >= 4
+            && statement[statement.len() - 4].is_punct("=")
+        {
+            aliases.insert(alias.text, &string.text[1..string.text.len() - 1]);
+        }
+    }
+    aliases
+}
+
+/// 按导入规则解析路径：先相对于 `base_dir`，再依次查找 `search_paths`
+///
+/// 返回规范化后的路径，文件不存在时返回 None。
+pub fn resolve_import(spec: &str, base_dir: &Path, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    std::iter::once(base_dir)
+        .chain(search_paths.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(spec))
+        .find(|candidate| candidate.is_file())
+        .map(|path| path.canonicalize().unwrap_or(path))
+}
+
+/// 解析文档中能找到目标文件的导入，返回 (导入表达式, 规范化后的目标路径)
+pub fn resolved_imports(
+    content: &str,
+    file_path: &Path,
+    search_paths: &[PathBuf],
+) -> Vec<(ImportSpecifier, PathBuf)> {
+    let base_dir = file_path.parent().unwrap_or(Path::new("."));
+    import_specifiers(content)
+        .into_iter()
+        .filter_map(|import| {
+            let target = resolve_import(&import.path, base_dir, search_paths)?;
+            Some((import, target))
+        })
+        .collect()
+}
+
+/// 读取模块内容：优先使用覆盖层中未保存的缓冲区，否则从磁盘读取
+pub fn read_module_source(canonical: &Path, overlay: &HashMap<PathBuf, String>) -> Option<String> {
+    match overlay.get(canonical) {
+        Some(content) => Some(content.clone()),
+        None => std::fs::read_to_string(canonical).ok(),
+    }
+}
+
+/// 从 `root` 出发直接或间接导入的所有模块（含 `root` 本身）
+pub fn reachable_modules(
+    root: &Path,
+    search_paths: &[PathBuf],
+    overlay: &HashMap<PathBuf, String>,
+) -> HashSet<PathBuf> {
+    let mut visited = HashSet::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+        let Some(content) = read_module_source(&path, overlay) else {
+            continue;
+        };
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        pending.extend(
+            import_specifiers(&content)
+                .iter()
+                .filter_map(|import| resolve_import(&import.path, base_dir, search_paths)),
+        );
+    }
+    visited
+}
+
+/// 按依赖顺序（被导入的模块在前）列出从 `root` 可达、需要构建的模块及其内容，不含 `root` 本身
+///
+/// `skip` 为 true 的模块（已缓存）不再读取，也不展开其导入；循环导入中回到祖先的边被忽略。
+pub fn build_order(
+    root: &Path,
+    root_content: &str,
+    search_paths: &[PathBuf],
+    overlay: &HashMap<PathBuf, String>,
+    skip: impl Fn(&Path) -> bool,
+) -> Vec<(PathBuf, String)> {
+    let imports_of = |path: &Path, content: &str| -> Vec<PathBuf> {
+        resolved_imports(content, path, search_paths)
+            .into_iter()
+            .map(|(_, target)| target)
+            .rev()
+            .collect()
+    };
+
+    let mut order = Vec::new();
+    let mut visited = HashSet::from([root.to_path_buf()]);
+    // (模块, 内容, 尚未访问的导入)
+    let mut stack = vec![(
+        root.to_path_buf(),
+        root_content.to_string(),
+        imports_of(root, root_content),
+    )];
+    while let Some((_, _, pending)) = stack.last_mut() {
+        match pending.pop() {
+            Some(next) => {
+                if skip(&next) || !visited.insert(next.clone()) {
+                    continue;
+                }
+                let Some(content) = read_module_source(&next, overlay) else {
+                    continue;
+                };
+                let imports = imports_of(&next, &content);
+                stack.push((next, content, imports));
+            }
+            None => {
+                let (path, content, _) = stack.pop().unwrap();
+                if stack.is_empty() {
+                    break;
+                }
+                order.push((path, content));
+            }
+        }
+    }
+    order
+}
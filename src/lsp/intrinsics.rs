@@ -0,0 +1,158 @@
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::utils::{offset_to_position, position_to_offset};
+
+/// 内建函数
+#[derive(Debug)]
+pub struct Intrinsic {
+    /// 调用时书写的名字（宏形式带 `!`）
+    pub name: &'static str,
+    /// 签名与说明，只收录能在标准库的用法中核实的内建函数；没有时只显示名字
+    pub doc: Option<IntrinsicDoc>,
+}
+
+/// 内建函数的签名与说明
+#[derive(Debug)]
+pub struct IntrinsicDoc {
+    /// (参数名, 参数约束)
+    pub params: &'static [(&'static str, &'static str)],
+    /// 返回值约束
+    pub returns: &'static str,
+    /// 副作用，纯函数为 None
+    pub effects: Option<&'static str>,
+    pub summary: &'static str,
+}
+
+/// 内建函数目录，顺序即补全列表中的顺序
+pub const INTRINSICS: &[Intrinsic] = &[
+    Intrinsic::undocumented("input!"),
+    Intrinsic {
+        name: "print!",
+        doc: Some(IntrinsicDoc {
+            params: &[("c", "char")],
+            returns: "()",
+            effects: Some("writes to standard output"),
+            summary: "Prints a single character. `print` and `println` in `string.mu` call it once per character of a string.",
+        }),
+    },
+    Intrinsic::undocumented("println!"),
+    Intrinsic::undocumented("flush!"),
+    Intrinsic::undocumented("panic_with!"),
+    Intrinsic::undocumented("repr!"),
+    Intrinsic::undocumented("display!"),
+    Intrinsic::undocumented("perform!"),
+    Intrinsic::undocumented("stopwatch!"),
+    Intrinsic::undocumented("__add!"),
+    Intrinsic::undocumented("__sub!"),
+    Intrinsic::undocumented("__mul!"),
+    Intrinsic::undocumented("__div!"),
+    Intrinsic::undocumented("__mod!"),
+    Intrinsic::undocumented("__is!"),
+    Intrinsic::undocumented("__greater!"),
+    Intrinsic::undocumented("__less!"),
+    Intrinsic::undocumented("__opcode!"),
+    Intrinsic::undocumented("__neg!"),
+    Intrinsic::undocumented("__assign!"),
+    Intrinsic::undocumented("__set_fixpoint!"),
+    Intrinsic::undocumented("__build_fixpoint!"),
+    Intrinsic::undocumented("__typeof"),
+];
+
+/// 所有内建函数的名字
+pub fn names() -> impl Iterator<Item = &'static str> {
+    INTRINSICS.iter().map(|intrinsic| intrinsic.name)
+}
+
+/// 是否为内建函数的名字
+pub fn is_intrinsic(name: &str) -> bool {
+    lookup(name).is_some()
+}
+
+/// 按名字查找内建函数
+pub fn lookup(name: &str) -> Option<&'static Intrinsic> {
+    INTRINSICS.iter().find(|intrinsic| intrinsic.name == name)
+}
+
+impl Intrinsic {
+    const fn undocumented(name: &'static str) -> Self {
+        Self { name, doc: None }
+    }
+
+    /// 形如 `print!(c: char) -> ()` 的签名；没有核实的签名时只有名字
+    pub fn signature(&self) -> String {
+        let Some(doc) = &self.doc else {
+            return self.name.to_string();
+        };
+        let params: Vec<String> = doc
+            .params
+            .iter()
+            .map(|(name, constraint)| format!("{}: {}", name, constraint))
+            .collect();
+        format!("{}({}) -> {}", self.name, params.join(", "), doc.returns)
+    }
+
+    /// 用于悬停与补全解析的 Markdown 文档
+    pub fn documentation(&self) -> String {
+        let Some(doc) = &self.doc else {
+            return format!("```mutica\n{}\n```\n\nBuilt-in intrinsic.", self.name);
+        };
+        format!(
+            "```mutica\n{}\n```\n\n{}\n\n---\n\n**Arity** {}\n\n**Effects** {}",
+            self.signature(),
+            doc.summary,
+            doc.params.len(),
+            doc.effects.unwrap_or("none (pure)")
+        )
+    }
+}
+
+/// 光标处的内建函数调用名及其范围
+///
+/// 宏形式的名字由标识符和紧随的 `!` 两个 token 组成。
+pub fn intrinsic_at(content: &str, position: Position) -> Option<(&'static Intrinsic, Range)> {
+    let offset = position_to_offset(content, position)?;
+    let tokens: Vec<Token> = Lexer::new(content).collect();
+    tokens.iter().enumerate().find_map(|(i, token)| {
+        if token.kind != TokenKind::Ident {
+            return None;
+        }
+        let end = match tokens.get(i + 1) {
+            Some(bang) if bang.is_punct("!") && bang.span.start == token.span.end => bang.span.end,
+            _ => token.span.end,
+        };
+        if !(token.span.start..=end).contains(&offset) {
+            return None;
+        }
+        let intrinsic = lookup(&content[token.span.start..end])?;
+        let range = Range::new(
+            offset_to_position(content, token.span.start),
+            offset_to_position(content, end),
+        );
+        Some((intrinsic, range))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique() {
+        let mut names: Vec<&str> = names().collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), INTRINSICS.len());
+    }
+
+    #[test]
+    fn undocumented_intrinsics_show_only_their_name() {
+        let intrinsic = lookup("__add!").unwrap();
+        assert_eq!(intrinsic.signature(), "__add!");
+        assert!(!intrinsic.documentation().contains("**Arity**"));
+        assert_eq!(
+            lookup("print!").unwrap().signature(),
+            "print!(c: char) -> ()"
+        );
+    }
+}
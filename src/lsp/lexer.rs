@@ -0,0 +1,218 @@
+use std::ops::Range;
+
+/// Mutica 关键字
+pub const KEYWORDS: &[&str] = &[
+    "let",
+    "with",
+    "match",
+    "rec",
+    "loop",
+    "panic",
+    "nat",
+    "char",
+    "float",
+    "true",
+    "false",
+    "any",
+    "unknown",
+    "never",
+    "import",
+    "if",
+    "then",
+    "else",
+    "handle",
+    "type",
+    "is",
+    "for",
+    "in",
+    "extend",
+    "sub",
+    "dyn_rec",
+    "where",
+    "exist",
+    "assert",
+    "constraint",
+    "mut",
+    "delay",
+    "typeof",
+];
+
+/// 多字符运算符，按长度降序排列以便最长匹配
+const MULTI_CHAR_PUNCTS: &[&str] = &[
+    "|->", "->", "=>", "::", "==", "!=", "<=", ">=", "|>", "..", ":=", "&&", "||",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// 普通标识符，包括 `$"op#add"` 这类引号标识符
+    Ident,
+    Keyword,
+    Number,
+    String,
+    Char,
+    LineComment,
+    BlockComment,
+    Punct,
+    /// 无法识别的字符，或未闭合的字符串/注释
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// 字节偏移范围
+    pub span: Range<usize>,
+}
+
+impl Token<'_> {
+    pub fn is_comment(&self) -> bool {
+        matches!(self.kind, TokenKind::LineComment | TokenKind::BlockComment)
+    }
+
+    pub fn is_punct(&self, punct: &str) -> bool {
+        self.kind == TokenKind::Punct && self.text == punct
+    }
+}
+
+/// 惰性词法分析器，按顺序产出 token（不包含空白）
+///
+/// 词法分析是容错的：即使源码存在语法错误也总能继续产出 token，
+/// 供不依赖完整 AST 的功能使用。
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self { src, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let src = self.src;
+        let mut pos = self.pos;
+
+        // 跳过空白
+        pos = scan_while(src, pos, char::is_whitespace);
+        if pos >= src.len() {
+            self.pos = pos;
+            return None;
+        }
+
+        let start = pos;
+        let ch = src[pos..].chars().next()?;
+
+        let kind = if src[pos..].starts_with("//") {
+            pos = src[pos..].find('\n').map_or(src.len(), |i| pos + i);
+            TokenKind::LineComment
+        } else if src[pos..].starts_with("/*") {
+            match src[pos + 2..].find("*/") {
+                Some(i) => {
+                    pos += 2 + i + 2;
+                    TokenKind::BlockComment
+                }
+                None => {
+                    pos = src.len();
+                    TokenKind::Unknown
+                }
+            }
+        } else if ch == '"' {
+            let (end, closed) = scan_quoted(src, pos, '"');
+            pos = end;
+            if closed {
+                TokenKind::String
+            } else {
+                TokenKind::Unknown
+            }
+        } else if ch == '$' && src[pos + 1..].starts_with('"') {
+            let (end, closed) = scan_quoted(src, pos + 1, '"');
+            pos = end;
+            if closed {
+                TokenKind::Ident
+            } else {
+                TokenKind::Unknown
+            }
+        } else if ch == '\'' {
+            let (end, closed) = scan_quoted(src, pos, '\'');
+            pos = end;
+            if closed {
+                TokenKind::Char
+            } else {
+                TokenKind::Unknown
+            }
+        } else if ch.is_ascii_digit() {
+            pos = scan_while(src, pos, |c| c.is_ascii_digit());
+            // 小数部分：`1.5`，但不吞掉 `1..2` 中的范围运算符
+            if src[pos..].starts_with('.')
+                && src[pos + 1..].starts_with(|c: char| c.is_ascii_digit())
+            {
+                pos = scan_while(src, pos + 1, |c| c.is_ascii_digit());
+            }
+            TokenKind::Number
+        } else if is_ident_start(ch) {
+            pos = scan_while(src, pos, is_ident_continue);
+            if KEYWORDS.contains(&&src[start..pos]) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Ident
+            }
+        } else if let Some(punct) = MULTI_CHAR_PUNCTS
+            .iter()
+            .find(|p| src[pos..].starts_with(**p))
+        {
+            pos += punct.len();
+            TokenKind::Punct
+        } else if ch.is_ascii_punctuation() {
+            pos += 1;
+            TokenKind::Punct
+        } else {
+            pos += ch.len_utf8();
+            TokenKind::Unknown
+        };
+
+        self.pos = pos;
+        Some(Token {
+            kind,
+            text: &src[start..pos],
+            span: start..pos,
+        })
+    }
+}
+
+pub fn is_ident_start(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
+
+pub fn is_ident_continue(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn scan_while(src: &str, mut pos: usize, pred: impl Fn(char) -> bool) -> usize {
+    for ch in src[pos..].chars() {
+        if !pred(ch) {
+            break;
+        }
+        pos += ch.len_utf8();
+    }
+    pos
+}
+
+/// 扫描以 `quote` 开头的引号字面量，返回 (结束偏移, 是否闭合)
+fn scan_quoted(src: &str, start: usize, quote: char) -> (usize, bool) {
+    let mut chars = src[start + 1..].char_indices();
+    while let Some((i, ch)) = chars.next() {
+        if ch == '\\' {
+            chars.next();
+        } else if ch == quote {
+            return (start + 1 + i + 1, true);
+        } else if ch == '\n' {
+            return (start + 1 + i, false);
+        }
+    }
+    (src.len(), false)
+}
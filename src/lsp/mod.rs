@@ -1,8 +1,24 @@
 pub mod ast_processor;
 pub mod backend;
+pub mod code_actions;
 pub mod completion;
+pub mod document;
+pub mod folding;
+pub mod formatter;
+pub mod highlight;
+pub mod hover;
+pub mod imports;
+pub mod intrinsics;
+pub mod lexer;
+pub mod modifiers;
+pub mod module_cache;
 pub mod references;
+pub mod scheduler;
+pub mod selection;
 pub mod semantic;
+pub mod signature;
+pub mod symbols;
 pub mod utils;
+pub mod workspace;
 
-pub use backend::Backend;
+pub use backend::{Backend, BackendState};
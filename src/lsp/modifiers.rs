@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range as ByteRange;
+
+use mutica::mutica_compiler::parser::{
+    WithLocation,
+    ast::{FlowedMetaData, LinearTypeAst},
+};
+use mutica::mutica_core::util::source_info::SourceFile;
+use tower_lsp::lsp_types::{Location, Range, SemanticTokenModifier, Url};
+
+use crate::lsp::ast_processor::child_nodes;
+use crate::lsp::highlight::is_assignment_target;
+use crate::lsp::intrinsics;
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::utils::position_to_offset;
+
+/// initialize 中声明的 token_modifiers，修饰符的位由其在此列表中的位置决定
+pub const LEGEND: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DEFINITION,
+    SemanticTokenModifier::READONLY,
+    SemanticTokenModifier::STATIC,
+    SemanticTokenModifier::DEPRECATED,
+    SemanticTokenModifier::ABSTRACT,
+    SemanticTokenModifier::ASYNC,
+    SemanticTokenModifier::MODIFICATION,
+    SemanticTokenModifier::DOCUMENTATION,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+];
+
+/// 修饰符在 `LEGEND` 中对应的位
+fn bit(modifier: SemanticTokenModifier) -> u32 {
+    let index = LEGEND
+        .iter()
+        .position(|m| *m == modifier)
+        .expect("modifier missing from LEGEND");
+    1 << index
+}
+
+/// 收集当前文件中引用标准库定义的变量范围
+///
+/// `inject_std_library` 注入的定义没有对应的文件路径，`collect_references` 不会登记它们。
+pub fn collect_default_library_spans(
+    root: &WithLocation<LinearTypeAst, FlowedMetaData>,
+    spans: &mut Vec<ByteRange<usize>>,
+    source_file: &SourceFile,
+) {
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        if let LinearTypeAst::Variable(_) = node.value()
+            && let Some(use_loc) = node.location()
+            && use_loc.source() == source_file
+            && let Some(def_loc) = node.payload().reference().and_then(|r| r.location())
+            && def_loc.source().path().is_none()
+        {
+            spans.push(use_loc.span());
+        }
+        pending.extend(child_nodes(node));
+    }
+}
+
+/// 按字节计算语义 token 的修饰符
+///
+/// - 绑定处（`is_declaration`）为 DECLARATION，`mut` 以外的绑定同时为 READONLY；
+/// - `x := ...`、`__assign!(x, ...)` 中被写入的变量为 MODIFICATION；
+/// - 标准库中的名字与内建函数为 DEFAULT_LIBRARY；
+/// - 其余解析到普通绑定的使用处为 READONLY。
+pub fn token_modifiers(
+    content: &str,
+    uri: &Url,
+    is_declaration: impl Fn(usize) -> bool,
+    references: &[(Range, Location)],
+    library_spans: &[ByteRange<usize>],
+) -> Vec<u32> {
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let is_mut_binder =
+        |i: usize| i > 0 && tokens[i - 1].kind == TokenKind::Keyword && tokens[i - 1].text == "mut";
+    // 起始偏移 -> token 下标
+    let token_at: HashMap<usize, usize> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.span.start, i))
+        .collect();
+    let declaration = bit(SemanticTokenModifier::DECLARATION);
+    let readonly = bit(SemanticTokenModifier::READONLY);
+    let modification = bit(SemanticTokenModifier::MODIFICATION);
+    let default_library = bit(SemanticTokenModifier::DEFAULT_LIBRARY);
+
+    // 当前文件中 `mut` 绑定的起始偏移
+    let mutable_definitions: HashSet<usize> = references
+        .iter()
+        .filter(|(_, def)| def.uri == *uri)
+        .filter_map(|(_, def)| position_to_offset(content, def.range.start))
+        .filter(|offset| token_at.get(offset).is_some_and(|&i| is_mut_binder(i)))
+        .collect();
+    // 使用处起始偏移 -> 是否解析到当前文件中的 `mut` 绑定
+    let resolved: HashMap<usize, bool> = references
+        .iter()
+        .filter_map(|(use_range, def)| {
+            let mutable = def.uri == *uri
+                && position_to_offset(content, def.range.start)
+                    .is_some_and(|offset| mutable_definitions.contains(&offset));
+            Some((position_to_offset(content, use_range.start)?, mutable))
+        })
+        .collect();
+    let library_starts: HashSet<usize> = library_spans.iter().map(|span| span.start).collect();
+
+    let mut modifiers = vec![0; content.len()];
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Ident {
+            continue;
+        }
+        let start = token.span.start;
+
+        // 内建函数的名字包含紧随的 `!`
+        let intrinsic_end = match tokens.get(i + 1) {
+            Some(bang) if bang.is_punct("!") && bang.span.start == token.span.end => bang.span.end,
+            _ => token.span.end,
+        };
+        if intrinsics::is_intrinsic(&content[start..intrinsic_end]) {
+            modifiers[start..intrinsic_end].fill(default_library);
+            continue;
+        }
+
+        let bits = if is_declaration(start) {
+            if is_mut_binder(i) {
+                declaration
+            } else {
+                declaration | readonly
+            }
+        } else if is_assignment_target(&tokens, i) {
+            modification
+        } else if library_starts.contains(&start) {
+            default_library | readonly
+        } else {
+            match resolved.get(&start) {
+                Some(false) => readonly,
+                Some(true) | None => 0,
+            }
+        };
+        modifiers[token.span.clone()].fill(bits);
+    }
+    modifiers
+}
@@ -0,0 +1,187 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::lsp::document::Overlay;
+use crate::lsp::imports::{import_specifiers, resolve_import};
+
+/// 跨多次分析复用的已解析模块
+///
+/// 来自编辑器缓冲区的模块按文档版本判断是否过期，来自磁盘的模块按内容哈希判断；
+/// 文件监视器报告变化时也会立即移除对应的模块。
+#[derive(Default)]
+pub struct ModuleCache {
+    /// 按规范化路径索引
+    entries: HashMap<PathBuf, CacheEntry>,
+    /// 每次有模块失效时递增，用于丢弃基于过期快照的构建结果
+    generation: u64,
+}
+
+impl fmt::Debug for ModuleCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModuleCache")
+            .field("modules", &self.entries.keys().collect::<Vec<_>>())
+            .field("generation", &self.generation)
+            .finish_non_exhaustive()
+    }
+}
+
+struct CacheEntry {
+    source: ModuleSource,
+    /// 该模块直接导入的模块（规范化路径），用于传递失效
+    imports: Vec<PathBuf>,
+    /// `MultiFileBuilder` 依赖表中的模块。mutica 没有公开该类型的名字，
+    /// 取出时按构建处推断出的类型还原
+    module: Box<dyn Any + Send>,
+}
+
+/// 模块内容的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModuleSource {
+    /// 编辑器中打开的文档及其版本
+    Buffer(i32),
+    /// 磁盘上的文件及构建时内容的哈希
+    Disk(u64),
+}
+
+/// 构建开始时取出的模块副本（按规范化路径索引），构建期间不持有缓存的锁
+pub struct CacheSnapshot<M> {
+    pub modules: HashMap<PathBuf, M>,
+    generation: u64,
+}
+
+impl ModuleCache {
+    /// 已缓存模块直接导入的模块
+    pub fn imports_of(&self, canonical: &Path) -> Option<Vec<PathBuf>> {
+        self.entries
+            .get(canonical)
+            .map(|entry| entry.imports.clone())
+    }
+
+    /// 移除本次分析用到的模块中（`overlay.modules`）已过期的模块，以及直接或间接导入它们的模块
+    ///
+    /// 缓冲区模块在版本变化或文档关闭时过期；磁盘模块在文档被打开、或 `disk_hashes`
+    /// （本次分析读取到的磁盘内容哈希）与构建时不一致时过期。其他文档依赖的模块不受影响。
+    pub fn invalidate(&mut self, overlay: &Overlay, disk_hashes: &HashMap<PathBuf, u64>) {
+        let stale: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|(path, _)| overlay.modules.contains(*path))
+            .filter(|(path, entry)| match entry.source {
+                ModuleSource::Buffer(version) => overlay.versions.get(*path) != Some(&version),
+                ModuleSource::Disk(hash) => {
+                    overlay.versions.contains_key(*path) || disk_hashes.get(*path) != Some(&hash)
+                }
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.remove_with_importers(stale);
+    }
+
+    /// 文件监视器报告磁盘上的文件被创建、修改或删除时调用
+    pub fn invalidate_path(&mut self, canonical: &Path) {
+        if self.entries.contains_key(canonical) {
+            self.remove_with_importers(vec![canonical.to_path_buf()]);
+        }
+    }
+
+    fn remove_with_importers(&mut self, mut stale: Vec<PathBuf>) {
+        if stale.is_empty() {
+            return;
+        }
+        self.generation += 1;
+        while let Some(path) = stale.pop() {
+            if self.entries.remove(&path).is_none() {
+                continue;
+            }
+            stale.extend(
+                self.entries
+                    .iter()
+                    .filter(|(_, other)| other.imports.contains(&path))
+                    .map(|(other_path, _)| other_path.clone()),
+            );
+        }
+    }
+
+    /// 复制本次分析会用到的模块（`modules` 中的规范化路径）
+    pub fn snapshot<M: Clone + 'static>(&self, modules: &HashSet<PathBuf>) -> CacheSnapshot<M> {
+        CacheSnapshot {
+            modules: self
+                .entries
+                .iter()
+                .filter(|(path, _)| modules.contains(*path))
+                .filter_map(|(path, entry)| {
+                    let module = entry.module.downcast_ref::<M>()?;
+                    Some((path.clone(), module.clone()))
+                })
+                .collect(),
+            generation: self.generation,
+        }
+    }
+
+    /// 构建结束后登记新构建的模块及其来源
+    ///
+    /// `contents` 为本次构建的模块所用的内容，磁盘模块按它计算哈希。
+    /// `failed` 中的模块不缓存，这样它们的错误会在下次分析时重新报告。
+    /// 取快照之后若有模块失效，本次构建的结果可能基于旧内容，整体丢弃。
+    pub fn record<M: Send + 'static>(
+        &mut self,
+        snapshot: CacheSnapshot<M>,
+        contents: &HashMap<PathBuf, String>,
+        failed: &HashSet<PathBuf>,
+        search_paths: &[PathBuf],
+        overlay: &Overlay,
+    ) {
+        if snapshot.generation != self.generation {
+            return;
+        }
+
+        for (path, module) in snapshot.modules {
+            if self.entries.contains_key(&path) || failed.contains(&path) {
+                continue;
+            }
+            let Some(content) = contents.get(&path) else {
+                continue;
+            };
+
+            let base_dir = path.parent().unwrap_or(Path::new("."));
+            let imports = import_specifiers(content)
+                .iter()
+                .filter_map(|import| resolve_import(&import.path, base_dir, search_paths))
+                .collect();
+            let source = match overlay.versions.get(&path) {
+                Some(&version) => ModuleSource::Buffer(version),
+                None => ModuleSource::Disk(content_hash(content)),
+            };
+            self.entries.insert(
+                path,
+                CacheEntry {
+                    source,
+                    imports,
+                    module: Box::new(module),
+                },
+            );
+        }
+    }
+}
+
+/// 读取本次分析用到的磁盘模块（不在覆盖层中的模块）并计算内容哈希，读取失败的模块不列出
+pub fn disk_hashes(overlay: &Overlay) -> HashMap<PathBuf, u64> {
+    overlay
+        .modules
+        .iter()
+        .filter(|path| !overlay.contents.contains_key(*path))
+        .filter_map(|path| {
+            let content = std::fs::read_to_string(path).ok()?;
+            Some((path.clone(), content_hash(&content)))
+        })
+        .collect()
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
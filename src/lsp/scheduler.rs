@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::sync::futures::Notified;
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::Url;
+
+/// 编辑后等待多久才开始分析，期间的新编辑会取代本次分析
+pub const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+/// 记录每个文档当前正在进行（或等待开始）的分析
+///
+/// 同一文档同一时刻只有一个有效的分析，登记新分析时旧分析的取消标记会被置位。
+/// 编辑触发的防抖分析作为后台任务由调度器持有。
+#[derive(Debug, Default)]
+pub struct AnalysisScheduler {
+    runs: Mutex<HashMap<Url, AnalysisRun>>,
+    tasks: Mutex<HashMap<Url, JoinHandle<()>>>,
+    finished: Notify,
+}
+
+#[derive(Debug)]
+struct AnalysisRun {
+    version: i32,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl AnalysisScheduler {
+    /// 为 `version` 登记一次新的分析并取消该文档上更旧的分析，返回本次分析的取消标记
+    ///
+    /// 如果同一版本的分析已在进行，返回 None，调用方应复用那次分析的结果。
+    pub fn begin(&self, uri: &Url, version: i32) -> Option<Arc<AtomicBool>> {
+        let mut runs = self.runs.lock().unwrap();
+        if let Some(run) = runs.get(uri) {
+            if run.version == version && !run.cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            run.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        runs.insert(
+            uri.clone(),
+            AnalysisRun {
+                version,
+                cancelled: cancelled.clone(),
+            },
+        );
+        Some(cancelled)
+    }
+
+    /// 文档的 `version` 是否有未被取消的分析正在进行（或等待开始）
+    pub fn is_running(&self, uri: &Url, version: i32) -> bool {
+        self.runs
+            .lock()
+            .unwrap()
+            .get(uri)
+            .is_some_and(|run| run.version == version && !run.cancelled.load(Ordering::Relaxed))
+    }
+
+    /// 分析结束后移除登记并唤醒等待者；如果它已被更新的分析取代则不移除登记
+    pub fn finish(&self, uri: &Url, cancelled: &Arc<AtomicBool>) {
+        {
+            let mut runs = self.runs.lock().unwrap();
+            if runs
+                .get(uri)
+                .is_some_and(|run| Arc::ptr_eq(&run.cancelled, cancelled))
+            {
+                runs.remove(uri);
+            }
+        }
+        self.finished.notify_waiters();
+    }
+
+    /// 下一次有分析结束时完成的 future
+    ///
+    /// 调用方应先 `enable` 再检查条件，避免错过检查与等待之间结束的分析。
+    pub fn finished(&self) -> Notified<'_> {
+        self.finished.notified()
+    }
+
+    /// 在后台运行文档的分析任务
+    ///
+    /// 旧任务不会被中止：它在登记新分析时已被取消，防抖结束后会自行退出。
+    pub fn spawn<F>(&self, uri: &Url, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(task);
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| !task.is_finished());
+        tasks.insert(uri.clone(), handle);
+    }
+
+    /// 取消文档上正在进行的分析并中止其后台任务（例如文档被关闭时）
+    pub fn cancel(&self, uri: &Url) {
+        if let Some(run) = self.runs.lock().unwrap().remove(uri) {
+            run.cancelled.store(true, Ordering::Relaxed);
+        }
+        if let Some(task) = self.tasks.lock().unwrap().remove(uri) {
+            task.abort();
+        }
+        self.finished.notify_waiters();
+    }
+}
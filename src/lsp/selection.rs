@@ -0,0 +1,82 @@
+use std::ops::Range as ByteRange;
+
+use mutica::mutica_compiler::parser::{
+    WithLocation,
+    ast::{FlowedMetaData, LinearTypeAst},
+};
+use mutica::mutica_core::util::source_info::SourceFile;
+use tower_lsp::lsp_types::{Position, Range, SelectionRange};
+
+use crate::lsp::ast_processor::child_nodes;
+use crate::lsp::utils::{offset_to_position, position_to_offset};
+
+/// 收集当前文件中所有 AST 节点的字节范围（已排序去重）
+///
+/// match 分支没有对应的节点，这里额外登记从模式开始到分支表达式结束的范围。
+pub fn collect_node_spans(
+    root: &WithLocation<LinearTypeAst, FlowedMetaData>,
+    spans: &mut Vec<ByteRange<usize>>,
+    source_file: &SourceFile,
+) {
+    let span_in_file = |node: &WithLocation<LinearTypeAst, FlowedMetaData>| {
+        node.location()
+            .filter(|loc| loc.source() == source_file)
+            .map(|loc| loc.span())
+    };
+
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        spans.extend(span_in_file(node));
+        if let LinearTypeAst::Match { branches, .. } = node.value() {
+            for (pattern, _, expr) in branches {
+                if let (Some(pattern), Some(expr)) = (span_in_file(pattern), span_in_file(expr)) {
+                    spans.push(pattern.start..expr.end);
+                }
+            }
+        }
+        pending.extend(child_nodes(node));
+    }
+
+    spans.retain(|span| span.start < span.end);
+    spans.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end)));
+    spans.dedup();
+}
+
+/// 由包含光标的节点范围构造从内到外的选择链
+///
+/// 只保留逐层嵌套的范围，光标不在任何节点内时返回光标处的空范围。
+pub fn selection_range(
+    content: &str,
+    spans: &[ByteRange<usize>],
+    position: Position,
+) -> SelectionRange {
+    let empty = SelectionRange {
+        range: Range::new(position, position),
+        parent: None,
+    };
+    let Some(offset) = position_to_offset(content, position) else {
+        return empty;
+    };
+
+    let mut containing: Vec<&ByteRange<usize>> = spans
+        .iter()
+        .filter(|span| span.start <= offset && offset <= span.end)
+        .collect();
+    containing.sort_by_key(|span| std::cmp::Reverse(span.len()));
+
+    let mut selection: Option<(SelectionRange, &ByteRange<usize>)> = None;
+    for span in containing {
+        if let Some((_, outer)) = &selection
+            && (span.start < outer.start || span.end > outer.end || span == *outer)
+        {
+            continue;
+        }
+        let range = Range::new(
+            offset_to_position(content, span.start),
+            offset_to_position(content, span.end),
+        );
+        let parent = selection.take().map(|(parent, _)| Box::new(parent));
+        selection = Some((SelectionRange { range, parent }, span));
+    }
+    selection.map_or(empty, |(selection, _)| selection)
+}
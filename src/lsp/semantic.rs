@@ -7,47 +7,358 @@ use mutica::mutica_core::util::colorize::TokenColor;
 use mutica::mutica_core::util::cycle_detector::FastCycleDetector;
 use mutica::mutica_core::util::source_info::SourceFile;
 use mutica::mutica_semantic::semantic::SourceMapping;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
-use tower_lsp::Client;
-use tower_lsp::jsonrpc::Result;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tower_lsp::lsp_types::*;
 
 use crate::lsp::ast_processor::perr_to_message;
+use crate::lsp::code_actions::{UNDECLARED_VARIABLE_CODE, UNUSED_VARIABLE_CODE};
+use crate::lsp::document::Overlay;
+use crate::lsp::folding::collect_match_spans;
+use crate::lsp::hover::{collect_declared_constraints, collect_hover_entry};
+use crate::lsp::imports::{build_order, import_specifiers, reachable_modules, resolve_import};
+use crate::lsp::modifiers::{collect_default_library_spans, token_modifiers};
+use crate::lsp::module_cache::{ModuleCache, disk_hashes};
 use crate::lsp::references::collect_references;
+use crate::lsp::selection::collect_node_spans;
+use crate::lsp::signature::{Signature, collect_signatures};
+use crate::lsp::symbols::collect_document_symbols;
 use crate::lsp::utils::{offset_to_position, report_to_plain_text};
 
-/// 解析文档并生成语义tokens,同时收集引用表和变量上下文映射
-pub async fn parse_and_generate_tokens(
+/// 一次文档分析的结果，`version` 为分析时的文档版本
+#[derive(Debug)]
+pub struct Analysis {
+    pub version: i32,
+    pub tokens: Option<SemanticTokens>,
+    pub reference_table: Vec<(Range, Location)>,
+    pub variable_map: Option<Vec<Option<Vec<String>>>>,
+    pub hover_table: Vec<(Range, String)>,
+    /// 当前文件中 match 表达式的字节范围，用于折叠
+    pub match_spans: Vec<std::ops::Range<usize>>,
+    /// 当前文件中 AST 节点的字节范围，用于扩展选区
+    pub node_spans: Vec<std::ops::Range<usize>>,
+    /// 当前文件中解析到函数定义的变量范围及其签名
+    pub signatures: Vec<(Range, Signature)>,
+    /// 由语法树生成的文档大纲
+    pub document_symbols: Vec<DocumentSymbol>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// 导入文件中的诊断，按文件 URI 分组
+    pub imported_diagnostics: HashMap<Url, Vec<Diagnostic>>,
+}
+
+impl Analysis {
+    fn failed(
+        version: i32,
+        diagnostics: Vec<Diagnostic>,
+        imported_diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    ) -> Self {
+        Self {
+            version,
+            tokens: None,
+            reference_table: Vec::new(),
+            variable_map: None,
+            hover_table: Vec::new(),
+            match_spans: Vec::new(),
+            node_spans: Vec::new(),
+            signatures: Vec::new(),
+            document_symbols: Vec::new(),
+            diagnostics,
+            imported_diagnostics,
+        }
+    }
+}
+
+/// 按文件收集诊断：当前文档的诊断与导入文件的诊断分开存放
+struct DiagnosticSink<'a> {
+    main_source: &'a SourceFile,
+    main: Vec<Diagnostic>,
+    imported: HashMap<Url, Vec<Diagnostic>>,
+}
+
+impl<'a> DiagnosticSink<'a> {
+    fn new(main_source: &'a SourceFile) -> Self {
+        Self {
+            main_source,
+            main: Vec::new(),
+            imported: HashMap::new(),
+        }
+    }
+
+    /// 没有位置信息的诊断归入当前文档；无法转换为文件 URI 的来源（如内置标准库）被忽略
+    fn push(&mut self, source: Option<&SourceFile>, diagnostic: Diagnostic) {
+        match source {
+            Some(source) if source != self.main_source => {
+                if let Ok(uri) = Url::from_file_path(source.filepath()) {
+                    self.imported.entry(uri).or_default().push(diagnostic);
+                }
+            }
+            _ => self.main.push(diagnostic),
+        }
+    }
+
+    /// 归入导入的文件 `path` 的诊断
+    fn push_to_file(&mut self, path: &Path, diagnostic: Diagnostic) {
+        if let Ok(uri) = Url::from_file_path(path) {
+            self.imported.entry(uri).or_default().push(diagnostic);
+        }
+    }
+
+    /// 在（直接或间接）导入了出错模块的 `import "..."` 表达式上附加一条诊断，
+    /// 通过 relatedInformation 指向真正的错误位置
+    fn finish(
+        mut self,
+        content: &str,
+        file_path: &Path,
+        search_paths: &[PathBuf],
+        overlay: &HashMap<PathBuf, String>,
+    ) -> (Vec<Diagnostic>, HashMap<Url, Vec<Diagnostic>>) {
+        let error_files: Vec<(PathBuf, &Url, &Vec<Diagnostic>)> = self
+            .imported
+            .iter()
+            .filter(|(_, diagnostics)| {
+                diagnostics
+                    .iter()
+                    .any(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+            })
+            .filter_map(|(uri, diagnostics)| {
+                let path = uri.to_file_path().ok()?;
+                Some((path.canonicalize().unwrap_or(path), uri, diagnostics))
+            })
+            .collect();
+
+        let base_dir = file_path.parent().unwrap_or(Path::new("."));
+        let mut import_diagnostics = Vec::new();
+        if !error_files.is_empty() {
+            for import in import_specifiers(content) {
+                let Some(target) = resolve_import(&import.path, base_dir, search_paths) else {
+                    continue;
+                };
+                let reachable = reachable_modules(&target, search_paths, overlay);
+                let related: Vec<DiagnosticRelatedInformation> = error_files
+                    .iter()
+                    .filter(|(path, _, _)| reachable.contains(path))
+                    .flat_map(|(_, uri, diagnostics)| {
+                        diagnostics
+                            .iter()
+                            .filter(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+                            .map(|d| DiagnosticRelatedInformation {
+                                location: Location {
+                                    uri: (*uri).clone(),
+                                    range: d.range,
+                                },
+                                message: d
+                                    .message
+                                    .lines()
+                                    .find(|line| !line.trim().is_empty())
+                                    .unwrap_or_default()
+                                    .trim()
+                                    .to_string(),
+                            })
+                    })
+                    .collect();
+                if related.is_empty() {
+                    continue;
+                }
+
+                import_diagnostics.push(Diagnostic {
+                    range: Range {
+                        start: offset_to_position(content, import.span.start),
+                        end: offset_to_position(content, import.span.end),
+                    },
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("mutica-lsp".to_string()),
+                    message: format!(
+                        "Imported module '{}' has {} error(s)",
+                        import.path,
+                        related.len()
+                    ),
+                    related_information: Some(related),
+                    ..Default::default()
+                });
+            }
+        }
+
+        self.main.extend(import_diagnostics);
+        (self.main, self.imported)
+    }
+}
+
+/// 解析文档并生成语义tokens,同时收集引用表、变量上下文映射、悬停信息和诊断
+///
+/// 导入路径相对于文档所在目录解析，找不到时依次查找 `search_paths`，
+/// 不依赖进程的工作目录，也不修改它。`overlay` 中的文件（编辑器中未保存的内容）优先于磁盘。
+/// 内容未变化的导入模块直接从 `module_cache` 复用。
+/// 每个阶段之间检查 `cancelled`，被取消时返回 None。
+pub fn parse_and_generate_tokens(
     content: &str,
     uri: &Url,
-    client: &Client,
-) -> Result<(
-    Option<SemanticTokens>,
-    Vec<(Range, Location)>,
-    Option<Vec<Option<Vec<String>>>>,
-)> {
+    version: i32,
+    search_paths: &[PathBuf],
+    overlay: Overlay,
+    module_cache: &Mutex<ModuleCache>,
+    cancelled: &AtomicBool,
+) -> Option<Analysis> {
     let file_path = if let Ok(path) = uri.to_file_path() {
         path
     } else {
         PathBuf::from(uri.path())
     };
 
-    // 1. 使用 MultiFileBuilder 构建 BasicTypeAst
-    let mut imported_ast = HashMap::new();
-    let mut path_detector = FastCycleDetector::new();
-    let mut builder_errors = Vec::new();
+    // 1. 使用 MultiFileBuilder 构建 BasicTypeAst，已缓存且未变化的导入模块不会重新解析
+    //
+    // 构建器相对于进程的工作目录读取导入的文件，因此导入由这里按文档所在目录和 `search_paths`
+    // 解析：从被导入的模块开始逐个构建，再把已构建的直接依赖放入构建器的依赖表。
+    // 依赖表同时以源码中书写的路径和规范化路径为键，构建器在表中找到模块时不会读取磁盘。
+    // 找不到、构建失败或处于循环导入中的导入以空模块占位，因此构建器永远不会自行读取磁盘，
+    // 这些导入的错误由这里报告。
+    // 只在取快照和登记结果时持有缓存的锁，构建期间其他文档的分析不必等待
+    let disk_hashes = disk_hashes(&overlay);
+    let mut snapshot = {
+        let mut cache = module_cache.lock().unwrap();
+        cache.invalidate(&overlay, &disk_hashes);
+        cache.snapshot(&overlay.modules)
+    };
 
-    let mut builder =
-        MultiFileBuilder::new(&mut imported_ast, &mut path_detector, &mut builder_errors);
+    let placeholder = MultiFileBuilder::new(
+        &mut HashMap::new(),
+        &mut FastCycleDetector::new(),
+        &mut Vec::new(),
+    )
+    .build(PathBuf::from("<unresolved import>"), "()".to_string())
+    .0
+    .expect("the placeholder module `()` always builds");
 
-    let (mut basic_ast_option, source) = builder.build(file_path.clone(), content.to_string());
+    // 返回 (模块, 来源, 构建器错误, 导入错误)
+    let build = |path: &Path,
+                 content: String,
+                 modules: &HashMap<PathBuf, _>,
+                 failed: &HashSet<PathBuf>| {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        let mut dependencies = HashMap::new();
+        let mut import_errors = Vec::new();
+        for import in import_specifiers(&content) {
+            let target = resolve_import(&import.path, base_dir, search_paths);
+            let module = match target.as_ref().and_then(|target| modules.get(target)) {
+                Some(module) => Clone::clone(module),
+                None => {
+                    let message = match &target {
+                        None => format!("Cannot find module '{}'", import.path),
+                        // 构建失败的模块的错误由 DiagnosticSink::finish 附加在导入处
+                        Some(target) if failed.contains(target) => String::new(),
+                        Some(_) => format!("Module '{}' is part of an import cycle", import.path),
+                    };
+                    if !message.is_empty() {
+                        import_errors.push(Diagnostic {
+                            range: Range::new(
+                                offset_to_position(&content, import.span.start),
+                                offset_to_position(&content, import.span.end),
+                            ),
+                            severity: Some(DiagnosticSeverity::ERROR),
+                            source: Some("mutica-lsp".to_string()),
+                            message,
+                            ..Default::default()
+                        });
+                    }
+                    Clone::clone(&placeholder)
+                }
+            };
+            if let Some(target) = target {
+                dependencies.insert(target, Clone::clone(&module));
+            }
+            dependencies.insert(PathBuf::from(&import.path), module);
+        }
+
+        let mut path_detector = FastCycleDetector::new();
+        let mut errors = Vec::new();
+        let (module, source) =
+            MultiFileBuilder::new(&mut dependencies, &mut path_detector, &mut errors)
+                .build(path.to_path_buf(), content);
+
+        // 其他模块的错误已在各自构建时报告，不再保留
+        errors.retain(|error| {
+            let Some(loc) = error.location() else {
+                return true;
+            };
+            let file = PathBuf::from(loc.source().filepath());
+            let file = file.canonicalize().unwrap_or(file);
+            file == canonical || !overlay.modules.contains(&file)
+        });
+        (module, source, errors, import_errors)
+    };
+
+    let root = file_path
+        .canonicalize()
+        .unwrap_or_else(|_| file_path.clone());
+    let pending = build_order(&root, content, search_paths, &overlay.contents, |path| {
+        snapshot.modules.contains_key(path)
+    });
+    let mut builder_errors = Vec::new();
+    // (所在文件, 诊断)
+    let mut import_errors = Vec::new();
+    let mut failed_modules = HashSet::new();
+    let mut built_contents = HashMap::new();
+    for (path, module_content) in pending {
+        built_contents.insert(path.clone(), module_content.clone());
+        let (module, _, errors, module_import_errors) =
+            build(&path, module_content, &snapshot.modules, &failed_modules);
+        builder_errors.extend(errors);
+        // 导入有错误的模块也不缓存，这样这些错误会在下次分析时重新报告
+        if !module_import_errors.is_empty() {
+            failed_modules.insert(path.clone());
+            import_errors.extend(module_import_errors.into_iter().map(|d| (path.clone(), d)));
+        }
+        match module {
+            Some(module) => {
+                snapshot.modules.insert(path, module);
+            }
+            None => {
+                failed_modules.insert(path);
+            }
+        }
+    }
+
+    let (mut basic_ast_option, source, errors, root_import_errors) = build(
+        &file_path,
+        content.to_string(),
+        &snapshot.modules,
+        &failed_modules,
+    );
+    builder_errors.extend(errors);
     if let Some((basic_ast, _)) = &mut basic_ast_option {
         *basic_ast = inject_std_library(basic_ast.clone(), &mut builder_errors);
     }
-    // 2. 统一处理所有构建过程中的错误
-    let mut diagnostics = Vec::new();
+
+    // 有错误的模块不缓存，这样它们的错误会在下次分析时重新报告
+    failed_modules.extend(
+        builder_errors
+            .iter()
+            .filter_map(|e| e.location())
+            .map(|loc| {
+                let path = PathBuf::from(loc.source().filepath());
+                path.canonicalize().unwrap_or(path)
+            }),
+    );
+    module_cache.lock().unwrap().record(
+        snapshot,
+        &built_contents,
+        &failed_modules,
+        search_paths,
+        &overlay,
+    );
+
+    if cancelled.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    // 2. 统一处理所有构建过程中的错误，按所在文件分组
+    let mut diagnostics = DiagnosticSink::new(source.as_ref());
+    diagnostics.main.extend(root_import_errors);
+    for (path, diagnostic) in import_errors {
+        diagnostics.push_to_file(&path, diagnostic);
+    }
     for builder_error in &builder_errors {
         if let Some(loc) = builder_error.location() {
             let error_file_path = loc.source().filepath();
@@ -105,13 +416,16 @@ pub async fn parse_and_generate_tokens(
                 }
             };
 
-            diagnostics.push(Diagnostic {
-                range,
-                severity: Some(DiagnosticSeverity::ERROR),
-                source: Some("mutica-lsp".to_string()),
-                message,
-                ..Default::default()
-            });
+            diagnostics.push(
+                Some(loc.source()),
+                Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("mutica-lsp".to_string()),
+                    message,
+                    ..Default::default()
+                },
+            );
         }
     }
 
@@ -140,25 +454,27 @@ pub async fn parse_and_generate_tokens(
                             start: offset_to_position(error_content, span.start),
                             end: offset_to_position(error_content, span.end),
                         };
-                        diagnostics.push(Diagnostic {
-                            range,
-                            severity: Some(DiagnosticSeverity::ERROR),
-                            source: Some("mutica-lsp".to_string()),
-                            message: format!(
-                                "Top-level binding error for variable '{}'",
-                                var.value()
-                            ),
-                            ..Default::default()
-                        });
+                        diagnostics.push(
+                            Some(loc.source()),
+                            Diagnostic {
+                                range,
+                                severity: Some(DiagnosticSeverity::ERROR),
+                                source: Some("mutica-lsp".to_string()),
+                                message: format!(
+                                    "Top-level binding error for variable '{}'",
+                                    var.value()
+                                ),
+                                ..Default::default()
+                            },
+                        );
                     }
                 }
             }
 
-            // 发送诊断信息并提前返回
-            client
-                .publish_diagnostics(uri.clone(), diagnostics, None)
-                .await;
-            return Ok((None, Vec::new(), None));
+            // 只返回诊断信息
+            let (diagnostics, imported_diagnostics) =
+                diagnostics.finish(content, &file_path, search_paths, &overlay.contents);
+            return Some(Analysis::failed(version, diagnostics, imported_diagnostics));
         }
 
         // 5. 语义分析和后续处理
@@ -177,6 +493,10 @@ pub async fn parse_and_generate_tokens(
             &mut semantic_errors,
         );
 
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+
         for e in semantic_errors {
             let file_path = e
                 .location()
@@ -187,28 +507,30 @@ pub async fn parse_and_generate_tokens(
                 .map(|loc| loc.source().content())
                 .unwrap_or(content);
             let err_report = e.report();
-            // Note: semantic errors are reported against the main file's content
+            // Note: semantic errors are reported against the error's own file content
             let cache = (
                 file_path,
                 mutica::mutica_compiler::ariadne::Source::from(content),
             );
             let plain = report_to_plain_text(|buf: &mut Vec<u8>| err_report.write(cache, buf));
 
+            // 每条诊断的范围按它所归入的文件计算：位置取自节点或变量名时以它们的来源为准，
+            // 没有位置时退回错误本身所在的文件
+            let error_source = e.location().map(|loc| loc.source());
             let mut error_items = Vec::new();
             match e.value() {
                 ParseError::OutgoingFixPointReference(ast, var, layer) => {
-                    if ast.location().is_none()
-                        || ast.location().unwrap().source() != source.as_ref()
-                    {
+                    if ast.location().is_none() {
                         continue;
                     }
                     let item = ast
                         .location()
                         .map(|loc| {
-                            let span = loc.span();
-                            let start = offset_to_position(content, span.start);
-                            let end = offset_to_position(content, span.end);
+                            let (loc_source, span) = (loc.source(), loc.span());
+                            let start = offset_to_position(loc_source.content(), span.start);
+                            let end = offset_to_position(loc_source.content(), span.end);
                             (
+                                Some(loc_source),
                                 Range { start, end },
                                 format!(
                                     "Fix-point variable '{}' referenced from {} layer(s) outside function scope",
@@ -218,6 +540,7 @@ pub async fn parse_and_generate_tokens(
                             )
                         })
                         .unwrap_or((
+                            error_source,
                             Range {
                                 start: Position::new(0, 0),
                                 end: offset_to_position(content, content.len()),
@@ -231,24 +554,24 @@ pub async fn parse_and_generate_tokens(
                     error_items.push(item);
                 }
                 ParseError::AstNotDesugared(ast) => {
-                    if ast.location().is_none()
-                        || ast.location().unwrap().source() != source.as_ref()
-                    {
+                    if ast.location().is_none() {
                         continue;
                     }
                     let item = ast
                         .location()
                         .map(|loc| {
-                            let span = loc.span();
-                            let start = offset_to_position(content, span.start);
-                            let end = offset_to_position(content, span.end);
+                            let (loc_source, span) = (loc.source(), loc.span());
+                            let start = offset_to_position(loc_source.content(), span.start);
+                            let end = offset_to_position(loc_source.content(), span.end);
                             (
+                                Some(loc_source),
                                 Range { start, end },
                                 "AST node not desugared properly".to_string(),
                                 DiagnosticSeverity::ERROR,
                             )
                         })
                         .unwrap_or((
+                            error_source,
                             Range {
                                 start: Position::new(0, 0),
                                 end: offset_to_position(content, content.len()),
@@ -259,53 +582,50 @@ pub async fn parse_and_generate_tokens(
                     error_items.push(item);
                 }
                 ParseError::UseBeforeDeclaration(ast, name) => {
-                    if ast.location().is_none()
-                        || ast.location().unwrap().source() != source.as_ref()
-                    {
+                    // 附带稳定的 code 和变量名，供自动导入快速修复使用
+                    let Some(loc) = ast.location() else {
                         continue;
-                    }
-                    let item = ast
-                        .location()
-                        .map(|loc| {
-                            let span = loc.span();
-                            let start = offset_to_position(content, span.start);
-                            let end = offset_to_position(content, span.end);
-                            (
-                                Range { start, end },
-                                format!("Use of undeclared variable '{}'", name.value()),
-                                DiagnosticSeverity::ERROR,
-                            )
-                        })
-                        .unwrap_or((
-                            Range {
-                                start: Position::new(0, 0),
-                                end: offset_to_position(content, content.len()),
+                    };
+                    let use_content = loc.source().content();
+                    let span = loc.span();
+                    diagnostics.push(
+                        Some(loc.source()),
+                        Diagnostic {
+                            range: Range {
+                                start: offset_to_position(use_content, span.start),
+                                end: offset_to_position(use_content, span.end),
                             },
-                            format!("Use of undeclared variable '{}'", name.value()),
-                            DiagnosticSeverity::ERROR,
-                        ));
-                    error_items.push(item);
+                            severity: Some(DiagnosticSeverity::ERROR),
+                            code: Some(NumberOrString::String(
+                                UNDECLARED_VARIABLE_CODE.to_string(),
+                            )),
+                            source: Some("mutica-lsp".to_string()),
+                            message: format!("Use of undeclared variable '{}'", name.value()),
+                            data: Some(serde_json::json!({ "name": name.value() })),
+                            ..Default::default()
+                        },
+                    );
                 }
                 ParseError::RedeclaredCaptureValue(ast, name) => {
-                    if ast.location().is_none()
-                        || ast.location().unwrap().source() != source.as_ref()
-                    {
+                    if ast.location().is_none() {
                         continue;
                     }
                     let item = name
                         .location()
                         .or_else(|| ast.location())
                         .map(|loc| {
-                            let span = loc.span();
-                            let start = offset_to_position(content, span.start);
-                            let end = offset_to_position(content, span.end);
+                            let (loc_source, span) = (loc.source(), loc.span());
+                            let start = offset_to_position(loc_source.content(), span.start);
+                            let end = offset_to_position(loc_source.content(), span.end);
                             (
+                                Some(loc_source),
                                 Range { start, end },
                                 format!("Redeclared capture variable '{}'", name.value()),
                                 DiagnosticSeverity::ERROR,
                             )
                         })
                         .unwrap_or((
+                            error_source,
                             Range {
                                 start: Position::new(0, 0),
                                 end: offset_to_position(content, content.len()),
@@ -316,57 +636,57 @@ pub async fn parse_and_generate_tokens(
                     error_items.push(item);
                 }
                 ParseError::UnusedVariable(_, names) => {
+                    // 直接生成诊断，附带稳定的 code 和变量名，供快速修复定位绑定
                     for name_loc in names {
-                        if name_loc.location().is_none()
-                            || name_loc.location().unwrap().source() != source.as_ref()
-                        {
+                        let Some(loc) = name_loc.location() else {
                             continue;
-                        }
-
-                        let item = name_loc
-                            .location()
-                            .map(|loc| {
-                                let span = loc.span();
-                                let start = offset_to_position(content, span.start);
-                                let end = offset_to_position(content, span.end);
-                                (
-                                    Range { start, end },
-                                    format!(
-                                        "Variable '{}' is declared but never used",
-                                        name_loc.value()
-                                    ),
-                                    DiagnosticSeverity::WARNING,
-                                )
-                            })
-                            .unwrap_or((
-                                Range {
-                                    start: Position::new(0, 0),
-                                    end: offset_to_position(content, content.len()),
+                        };
+                        let name_content = loc.source().content();
+                        let span = loc.span();
+                        diagnostics.push(
+                            Some(loc.source()),
+                            Diagnostic {
+                                range: Range {
+                                    start: offset_to_position(name_content, span.start),
+                                    end: offset_to_position(name_content, span.end),
                                 },
-                                "Variable is declared but never used".to_string(),
-                                DiagnosticSeverity::WARNING,
-                            ));
-                        error_items.push(item);
+                                severity: Some(DiagnosticSeverity::WARNING),
+                                code: Some(NumberOrString::String(
+                                    UNUSED_VARIABLE_CODE.to_string(),
+                                )),
+                                source: Some("mutica-lsp".to_string()),
+                                message: format!(
+                                    "Variable '{}' is declared but never used",
+                                    name_loc.value()
+                                ),
+                                data: Some(serde_json::json!({ "name": name_loc.value() })),
+                                ..Default::default()
+                            },
+                        );
                     }
                 }
                 ParseError::AmbiguousPattern(ast)
                 | ParseError::PatternOutOfParameterDefinition(ast)
                 | ParseError::MissingBranch(ast) => {
-                    if ast.location().is_none()
-                        || ast.location().unwrap().source() != source.as_ref()
-                    {
+                    if ast.location().is_none() {
                         continue;
                     }
                     let item = ast
                         .location()
                         .map(|loc| {
-                            let span = loc.span();
-                            let start = offset_to_position(content, span.start);
-                            let end = offset_to_position(content, span.end);
+                            let (loc_source, span) = (loc.source(), loc.span());
+                            let start = offset_to_position(loc_source.content(), span.start);
+                            let end = offset_to_position(loc_source.content(), span.end);
                             let msg = perr_to_message(&e).unwrap_or_else(|| plain.clone());
-                            (Range { start, end }, msg, DiagnosticSeverity::ERROR)
+                            (
+                                Some(loc_source),
+                                Range { start, end },
+                                msg,
+                                DiagnosticSeverity::ERROR,
+                            )
                         })
                         .unwrap_or((
+                            error_source,
                             Range {
                                 start: Position::new(0, 0),
                                 end: offset_to_position(content, content.len()),
@@ -380,6 +700,7 @@ pub async fn parse_and_generate_tokens(
                     let start = Position::new(0, 0);
                     let end = offset_to_position(content, content.len());
                     error_items.push((
+                        error_source,
                         Range { start, end },
                         msg.clone(),
                         DiagnosticSeverity::ERROR,
@@ -387,34 +708,62 @@ pub async fn parse_and_generate_tokens(
                 }
             }
 
-            for (range, message, severity) in error_items {
-                diagnostics.push(Diagnostic {
-                    range,
-                    severity: Some(severity),
-                    source: Some("mutica-lsp".to_string()),
-                    message,
-                    ..Default::default()
-                });
+            // 错误归入它所在的文件，导入文件中的错误不再丢弃
+            for (item_source, range, message, severity) in error_items {
+                diagnostics.push(
+                    item_source,
+                    Diagnostic {
+                        range,
+                        severity: Some(severity),
+                        source: Some("mutica-lsp".to_string()),
+                        message,
+                        ..Default::default()
+                    },
+                );
             }
         }
 
-        client
-            .publish_diagnostics(uri.clone(), diagnostics, None)
-            .await;
+        let (diagnostics, imported_diagnostics) =
+            diagnostics.finish(content, &file_path, search_paths, &overlay.contents);
 
         // 5. 生成语义 Token 和引用
         let mut reference_table = Vec::new();
         collect_references(flowed_result.ty(), &mut reference_table, source.as_ref());
+        let mut match_spans = Vec::new();
+        collect_match_spans(flowed_result.ty(), &mut match_spans, source.as_ref());
+        let mut node_spans = Vec::new();
+        collect_node_spans(flowed_result.ty(), &mut node_spans, source.as_ref());
+        let mut library_spans = Vec::new();
+        collect_default_library_spans(flowed_result.ty(), &mut library_spans, source.as_ref());
+        let document_symbols = collect_document_symbols(flowed_result.ty(), source.as_ref());
+        let signatures = collect_signatures(flowed_result.ty(), source.as_ref());
 
         let source_file = Arc::new(SourceFile::new(Some(file_path), content.to_string()));
         let mapping = SourceMapping::from_ast(flowed_result.ty(), &source_file);
 
         // 提取变量上下文映射：用 Vec 按字节偏移存储变量列表
+        // 同时为每个最内层节点生成一次悬停信息
         let content_len = content.len();
         let mut variable_vec: Vec<Option<Vec<String>>> = vec![None; content_len];
+        let mut hover_table = Vec::new();
+        let mut hover_spans = HashSet::new();
+        let mut visited_spans = HashSet::new();
+        let mut constraints = HashMap::new();
+        collect_declared_constraints(flowed_result.ty(), &mut constraints);
 
         for (offset, node_opt) in mapping.mapping().iter().enumerate() {
             if let Some(node) = node_opt {
+                if let Some(loc) = node.location()
+                    && visited_spans.insert((loc.span().start, loc.span().end))
+                {
+                    collect_hover_entry(
+                        node,
+                        &mut hover_table,
+                        &mut hover_spans,
+                        source.as_ref(),
+                        &constraints,
+                    );
+                }
                 let variables: Vec<String> = node
                     .payload()
                     .variable_context()
@@ -427,6 +776,19 @@ pub async fn parse_and_generate_tokens(
             }
         }
 
+        let modifiers = token_modifiers(
+            content,
+            uri,
+            |offset| {
+                matches!(
+                    basic_ast.1.color_mapping().get(offset),
+                    Some(TokenColor::Declaration)
+                )
+            },
+            &reference_table,
+            &library_spans,
+        );
+
         let mut tokens = Vec::new();
         let mut last_line = 0u32;
         let mut last_start = 0u32;
@@ -443,7 +805,8 @@ pub async fn parse_and_generate_tokens(
             // so we must iterate by characters and compute token runs in UTF-16 units while querying
             // the color mapping by the character's starting byte offset.
 
-            let mut current_type: Option<u32> = None;
+            // 当前连续片段的 (token 类型, 修饰符)
+            let mut current_type: Option<(u32, u32)> = None;
             let mut run_utf16_len: u32 = 0; // length of current run in UTF-16 code units
             let mut run_start_utf16_col: u32 = 0; // start column (utf-16 units) of current run relative to line
 
@@ -459,13 +822,14 @@ pub async fn parse_and_generate_tokens(
                     .get(abs_byte)
                     .map(color_to_token_type)
                     .unwrap_or(17);
+                let ty = (ty, modifiers[abs_byte]);
 
                 // number of UTF-16 code units for this char
                 let ch_utf16 = ch.encode_utf16(&mut [0u16; 2]).len() as u32;
 
                 if current_type != Some(ty) {
                     // flush previous run
-                    if let Some(typ) = current_type {
+                    if let Some((typ, modifier_bits)) = current_type {
                         let delta_line = line_num as u32 - last_line;
                         let delta_start = if delta_line == 0 {
                             run_start_utf16_col.saturating_sub(last_start)
@@ -477,7 +841,7 @@ pub async fn parse_and_generate_tokens(
                             delta_start,
                             length: run_utf16_len,
                             token_type: typ,
-                            token_modifiers_bitset: 0,
+                            token_modifiers_bitset: modifier_bits,
                         });
                         last_line = line_num as u32;
                         last_start = run_start_utf16_col;
@@ -496,7 +860,7 @@ pub async fn parse_and_generate_tokens(
 
             // flush remaining run at end of line
             match current_type {
-                Some(typ) if run_utf16_len > 0 => {
+                Some((typ, modifier_bits)) if run_utf16_len > 0 => {
                     let delta_line = line_num as u32 - last_line;
                     let delta_start = if delta_line == 0 {
                         run_start_utf16_col.saturating_sub(last_start)
@@ -508,7 +872,7 @@ pub async fn parse_and_generate_tokens(
                         delta_start,
                         length: run_utf16_len,
                         token_type: typ,
-                        token_modifiers_bitset: 0,
+                        token_modifiers_bitset: modifier_bits,
                     });
                     last_line = line_num as u32;
                     last_start = run_start_utf16_col;
@@ -519,20 +883,27 @@ pub async fn parse_and_generate_tokens(
             byte_offset = line_end + 1;
         }
 
-        Ok((
-            Some(SemanticTokens {
+        Some(Analysis {
+            version,
+            tokens: Some(SemanticTokens {
                 result_id: None,
                 data: tokens,
             }),
             reference_table,
-            Some(variable_vec),
-        ))
+            variable_map: Some(variable_vec),
+            hover_table,
+            match_spans,
+            node_spans,
+            signatures,
+            document_symbols,
+            diagnostics,
+            imported_diagnostics,
+        })
     } else {
-        // 如果构建失败,发送诊断信息并提前返回
-        client
-            .publish_diagnostics(uri.clone(), diagnostics, None)
-            .await;
-        Ok((None, Vec::new(), None))
+        // 如果构建失败,只返回诊断信息
+        let (diagnostics, imported_diagnostics) =
+            diagnostics.finish(content, &file_path, search_paths, &overlay.contents);
+        Some(Analysis::failed(version, diagnostics, imported_diagnostics))
     }
 }
 
@@ -558,3 +929,181 @@ fn color_to_token_type(color: &TokenColor) -> u32 {
         TokenColor::Error => 17,       // COMMENT (error as fallback)
     }
 }
+
+/// 新旧语义 token 之间的差异：去掉相同的前缀和后缀后，替换中间的部分
+///
+/// `start` 和 `delete_count` 以整数为单位，每个 token 占 5 个整数。
+pub fn semantic_token_edits(
+    old: &[SemanticToken],
+    new: &[SemanticToken],
+) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let deleted = old.len() - prefix - suffix;
+    let inserted = &new[prefix..new.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: (deleted * 5) as u32,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+/// 截取与 `range` 相交的语义 token，并重新编码为相对位置
+pub fn semantic_tokens_in_range(tokens: &[SemanticToken], range: Range) -> Vec<SemanticToken> {
+    let mut result = Vec::new();
+    let (mut line, mut start) = (0u32, 0u32);
+    let (mut last_line, mut last_start) = (0u32, 0u32);
+    for token in tokens {
+        line += token.delta_line;
+        start = if token.delta_line == 0 {
+            start + token.delta_start
+        } else {
+            token.delta_start
+        };
+        let token_start = Position::new(line, start);
+        let token_end = Position::new(line, start + token.length);
+        if token_end <= range.start || token_start >= range.end {
+            continue;
+        }
+
+        let delta_line = line - last_line;
+        result.push(SemanticToken {
+            delta_line,
+            delta_start: if delta_line == 0 {
+                start - last_start
+            } else {
+                start
+            },
+            ..*token
+        });
+        (last_line, last_start) = (line, start);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(delta_line: u32, delta_start: u32, length: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    /// 按客户端的方式把增量应用到扁平的整数数组上
+    fn apply(old: &[SemanticToken], edits: &[SemanticTokensEdit]) -> Vec<SemanticToken> {
+        let flatten = |tokens: &[SemanticToken]| -> Vec<u32> {
+            tokens
+                .iter()
+                .flat_map(|t| {
+                    [
+                        t.delta_line,
+                        t.delta_start,
+                        t.length,
+                        t.token_type,
+                        t.token_modifiers_bitset,
+                    ]
+                })
+                .collect()
+        };
+        let mut data = flatten(old);
+        for edit in edits.iter().rev() {
+            let start = edit.start as usize;
+            let inserted = flatten(edit.data.as_deref().unwrap_or_default());
+            data.splice(start..start + edit.delete_count as usize, inserted);
+        }
+        data.chunks(5)
+            .map(|c| SemanticToken {
+                delta_line: c[0],
+                delta_start: c[1],
+                length: c[2],
+                token_type: c[3],
+                token_modifiers_bitset: c[4],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_from_an_empty_previous_result_insert_everything() {
+        let new = [token(0, 0, 3), token(1, 4, 1)];
+        let edits = semantic_token_edits(&[], &new);
+        assert_eq!(edits.len(), 1);
+        assert_eq!((edits[0].start, edits[0].delete_count), (0, 0));
+        assert_eq!(apply(&[], &edits), new);
+    }
+
+    #[test]
+    fn unchanged_tokens_produce_no_edits() {
+        let tokens = [token(0, 0, 3), token(1, 4, 1)];
+        assert!(semantic_token_edits(&tokens, &tokens).is_empty());
+        assert!(semantic_token_edits(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn edits_replace_only_the_changed_middle() {
+        let old = [token(0, 0, 3), token(0, 4, 1), token(1, 0, 2)];
+        let new = [
+            token(0, 0, 3),
+            token(0, 4, 5),
+            token(0, 6, 1),
+            token(1, 0, 2),
+        ];
+        let edits = semantic_token_edits(&old, &new);
+        assert_eq!(edits.len(), 1);
+        // 偏移和删除数量以整数计，每个 token 5 个整数
+        assert_eq!((edits[0].start, edits[0].delete_count), (5, 5));
+        assert_eq!(apply(&old, &edits), new);
+
+        // 删除到空数组
+        let edits = semantic_token_edits(&old, &[]);
+        assert_eq!((edits[0].start, edits[0].delete_count), (0, 15));
+        assert!(apply(&old, &edits).is_empty());
+    }
+
+    #[test]
+    fn range_keeps_tokens_that_straddle_its_boundaries() {
+        // 绝对位置：(0, 2..5)、(1, 0..4)、(1, 6..8)、(3, 1..2)
+        let tokens = [
+            token(0, 2, 3),
+            token(1, 0, 4),
+            token(0, 6, 2),
+            token(2, 1, 1),
+        ];
+        let range = Range::new(Position::new(0, 4), Position::new(1, 7));
+        // 第一个 token 跨过范围起点、第三个跨过范围终点，都保留；重新编码时首个 token 相对于文档开头
+        assert_eq!(
+            semantic_tokens_in_range(&tokens, range),
+            [token(0, 2, 3), token(1, 0, 4), token(0, 6, 2)]
+        );
+    }
+
+    #[test]
+    fn range_reencodes_relative_to_the_first_kept_token() {
+        let tokens = [
+            token(0, 2, 3),
+            token(1, 0, 4),
+            token(0, 6, 2),
+            token(2, 1, 1),
+        ];
+        // 只与 (1, 6..8) 相交，紧挨着的 (1, 0..4) 结束于范围起点之前
+        let range = Range::new(Position::new(1, 5), Position::new(2, 0));
+        assert_eq!(semantic_tokens_in_range(&tokens, range), [token(1, 6, 2)]);
+        // 结束于范围起点、开始于范围终点的 token 都不相交
+        let range = Range::new(Position::new(0, 5), Position::new(1, 0));
+        assert!(semantic_tokens_in_range(&tokens, range).is_empty());
+        assert!(semantic_tokens_in_range(&[], range).is_empty());
+    }
+}
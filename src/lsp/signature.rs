@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use mutica::mutica_compiler::parser::{
+    WithLocation,
+    ast::{FlowedMetaData, LinearTypeAst},
+};
+use mutica::mutica_core::util::source_info::SourceFile;
+use tower_lsp::lsp_types::{
+    DocumentSymbol, ParameterInformation, ParameterLabel, Range as LspRange, SignatureHelp,
+    SignatureInformation, SymbolKind,
+};
+
+use crate::lsp::ast_processor::child_nodes;
+use crate::lsp::hover::render_constraint;
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::symbols::scan_symbols;
+use crate::lsp::utils::{offset_to_position, position_to_offset};
+
+type FlowedNode = WithLocation<LinearTypeAst, FlowedMetaData>;
+
+/// 光标所在的调用位置
+#[derive(Debug)]
+pub struct CallSite {
+    pub callee: String,
+    /// 被调用标识符的字节范围
+    pub callee_span: Range<usize>,
+    /// 在当前括号之前已经应用过的参数组数，即柯里化链中的活动参数
+    pub applied: usize,
+    /// 当前括号内光标之前的逗号数，即元组参数中的活动元素
+    pub element: usize,
+}
+
+/// 签名中的一个参数，`element` 为元组参数中的元素序号
+struct Parameter {
+    curried: usize,
+    element: Option<usize>,
+    label: Range<usize>,
+}
+
+/// 从光标处向前查找尚未闭合的调用括号，例如 `take(drop(s)(start))(|`
+pub fn find_call_site(content: &str, offset: usize) -> Option<CallSite> {
+    let tokens: Vec<Token> = Lexer::new(content.get(..offset)?)
+        .filter(|t| t.kind != TokenKind::BlockComment)
+        .collect();
+
+    // 光标位于行注释、未闭合的字符串或注释中时不提供签名
+    if tokens
+        .last()
+        .is_some_and(|t| matches!(t.kind, TokenKind::LineComment | TokenKind::Unknown))
+    {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut element = 0;
+    let mut open = None;
+    for (i, token) in tokens.iter().enumerate().rev() {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            ")" | "]" | "}" => depth += 1,
+            "(" if depth == 0 => {
+                open = Some(i);
+                break;
+            }
+            "[" | "{" if depth == 0 => return None,
+            "(" | "[" | "{" => depth -= 1,
+            "," if depth == 0 => element += 1,
+            ";" if depth == 0 => return None,
+            _ => {}
+        }
+    }
+
+    // 跳过之前已经应用的参数组：`f(a)(b)(|`
+    let mut callee = open?.checked_sub(1)?;
+    let mut applied = 0;
+    while tokens[callee].is_punct(")") {
+        callee = matching_open(&tokens, callee)?.checked_sub(1)?;
+        applied += 1;
+    }
+
+    let callee_token = &tokens[callee];
+    if callee_token.kind != TokenKind::Ident {
+        return None;
+    }
+    Some(CallSite {
+        callee: callee_token.text.to_string(),
+        callee_span: callee_token.span.clone(),
+        applied,
+        element,
+    })
+}
+
+/// 定义的签名，由语法树或定义处的源码得到
+#[derive(Debug, Clone)]
+pub struct Signature {
+    name: String,
+    groups: Vec<ParameterGroup>,
+}
+
+/// 柯里化链中的一组参数
+#[derive(Debug, Clone)]
+enum ParameterGroup {
+    /// `constraint (a, b) => ...` 的参数模式，元组模式的每个元素单独作为参数
+    Pattern(Vec<String>),
+    /// 泛型的约束变量 `name: constraint`
+    Generic(Vec<String>),
+    /// 多分支 `match` 的各分支模式，整体作为一个参数
+    Arms(Vec<String>),
+}
+
+/// 遍历分析后的语法树，为当前文件中每个解析到函数定义的变量登记签名
+///
+/// 定义包括 `loop`/`rec`/`dyn_rec` 不动点和 `let` 绑定；`let x = v; e` 在语法树中是
+/// 单分支 `match` 作用于 `v`，绑定的值即调用的参数。返回 (使用处范围, 签名)。
+pub fn collect_signatures(
+    root: &FlowedNode,
+    source_file: &SourceFile,
+) -> Vec<(LspRange, Signature)> {
+    let mut definitions: HashMap<(String, usize, usize), Signature> = HashMap::new();
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        let definition = match node.value() {
+            LinearTypeAst::StaticFixPoint { var, expr } => Some((var, expr.as_ref())),
+            LinearTypeAst::Invoke { func, arg, .. } => match func.value() {
+                LinearTypeAst::Match { branches, .. } if branches.len() == 1 => {
+                    match branches[0].0.value() {
+                        LinearTypeAst::Bind { var, .. } => Some((var, arg.as_ref())),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some((var, value)) = definition
+            && let Some(loc) = var.location()
+        {
+            let groups = parameter_groups(value);
+            if !groups.is_empty() {
+                let span = loc.span();
+                definitions.insert(
+                    (loc.source().filepath(), span.start, span.end),
+                    Signature {
+                        name: var.value().clone(),
+                        groups,
+                    },
+                );
+            }
+        }
+        pending.extend(child_nodes(node));
+    }
+
+    let content = source_file.content();
+    let mut signatures = Vec::new();
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        if let LinearTypeAst::Variable(_) = node.value()
+            && let Some(use_loc) = node.location()
+            && use_loc.source() == source_file
+            && let Some(def_loc) = node.payload().reference().and_then(|r| r.location())
+        {
+            let def_span = def_loc.span();
+            if let Some(signature) =
+                definitions.get(&(def_loc.source().filepath(), def_span.start, def_span.end))
+            {
+                let span = use_loc.span();
+                let range = LspRange::new(
+                    offset_to_position(content, span.start),
+                    offset_to_position(content, span.end),
+                );
+                signatures.push((range, signature.clone()));
+            }
+        }
+        pending.extend(child_nodes(node));
+    }
+    signatures
+}
+
+/// 沿定义的值收集参数链：不动点取其主体，`Generic` 取约束变量，
+/// 单分支 `match` 取分支模式并继续深入分支体，多分支 `match` 取全部分支模式后结束
+fn parameter_groups(value: &FlowedNode) -> Vec<ParameterGroup> {
+    let mut groups = Vec::new();
+    let mut node = value;
+    loop {
+        match node.value() {
+            LinearTypeAst::StaticFixPoint { expr, .. } => node = expr,
+            LinearTypeAst::Generic {
+                expr,
+                constraint,
+                hidden,
+            } => {
+                // 约束变量出现在紧随的分支模式中时以模式为准
+                if !hidden
+                    && !constraint.is_empty()
+                    && !matches!(expr.value(), LinearTypeAst::Match { .. })
+                {
+                    groups.push(ParameterGroup::Generic(
+                        constraint
+                            .iter()
+                            .map(|(name, c)| format!("{}: {}", name, render_constraint(c)))
+                            .collect(),
+                    ));
+                }
+                node = expr;
+            }
+            LinearTypeAst::Match { branches, .. } if branches.len() == 1 => {
+                let (pattern, _, body) = &branches[0];
+                let elements = match pattern.value() {
+                    LinearTypeAst::Tuple(items) if items.len() > 1 => items
+                        .iter()
+                        .map(|(item, _)| render_constraint(item))
+                        .collect(),
+                    _ => vec![render_constraint(pattern)],
+                };
+                groups.push(ParameterGroup::Pattern(elements));
+                node = body;
+            }
+            LinearTypeAst::Match { branches, .. } if !branches.is_empty() => {
+                groups.push(ParameterGroup::Arms(
+                    branches
+                        .iter()
+                        .map(|(pattern, _, _)| render_constraint(pattern))
+                        .collect(),
+                ));
+                break;
+            }
+            _ => break,
+        }
+    }
+    groups
+}
+
+/// 在当前文档中查找 `name` 在 `before` 之前最近的一个定义，返回定义名的字节偏移
+///
+/// 仅在语法树不可用（例如正在输入调用、文档无法解析）时作为后备方案。
+pub fn find_local_definition(content: &str, name: &str, before: usize) -> Option<usize> {
+    fn visit(symbols: &[DocumentSymbol], name: &str, content: &str, found: &mut Vec<usize>) {
+        for symbol in symbols {
+            if symbol.name == name
+                && symbol.kind != SymbolKind::FIELD
+                && let Some(offset) = position_to_offset(content, symbol.selection_range.start)
+            {
+                found.push(offset);
+            }
+            if let Some(children) = &symbol.children {
+                visit(children, name, content, found);
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    visit(&scan_symbols(content), name, content, &mut found);
+    found.into_iter().filter(|&offset| offset < before).max()
+}
+
+/// 根据定义处的源码得到签名，与 `find_local_definition` 一起作为后备方案
+///
+/// 参数链取自定义值开头的 `constraint <pattern> =>` 序列；`loop` 的参数为其模式，
+/// 无被匹配值的 `match` 则把所有分支模式作为一个参数。
+pub fn lexical_signature(content: &str, def_offset: usize) -> Option<Signature> {
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let name_index = tokens.iter().position(|t| t.span.start == def_offset)?;
+    if !tokens.get(name_index + 1)?.is_punct(":") {
+        return None;
+    }
+
+    let mut groups = Vec::new();
+    match name_index.checked_sub(1).map(|i| tokens[i].text) {
+        Some("loop") => {
+            // `loop go: constraint t: any = init;`
+            let mut start = name_index + 2;
+            if tokens.get(start)?.text == "constraint" {
+                start += 1;
+            }
+            let end = scan_pattern(&tokens, start, &["="])?;
+            groups.extend(pattern_group(content, &tokens[start..end]));
+        }
+        Some("rec" | "dyn_rec" | "extend") => {
+            parse_chain(content, &tokens, name_index + 2, &mut groups);
+        }
+        _ => {
+            // `name: <constraint> = <value>`，参数绑定（以 `=>`、`,` 等结尾）没有签名
+            let eq = scan_pattern(&tokens, name_index + 2, &["="])?;
+            parse_chain(content, &tokens, eq + 1, &mut groups);
+        }
+    }
+
+    (!groups.is_empty()).then(|| Signature {
+        name: tokens[name_index].text.to_string(),
+        groups,
+    })
+}
+
+/// 解析 `constraint a => constraint b => ...` 参数链
+fn parse_chain(
+    content: &str,
+    tokens: &[Token],
+    mut index: usize,
+    groups: &mut Vec<ParameterGroup>,
+) {
+    while let Some(token) = tokens.get(index) {
+        match token.text {
+            // 包裹在不动点中的函数：`dyn_rec go: match ...`
+            "rec" | "dyn_rec"
+                if token.kind == TokenKind::Keyword
+                    && tokens.get(index + 2).is_some_and(|t| t.is_punct(":")) =>
+            {
+                index += 3;
+            }
+            "constraint" if token.kind == TokenKind::Keyword => {
+                let Some(arrow) = scan_pattern(tokens, index + 1, &["=>"]) else {
+                    break;
+                };
+                groups.extend(pattern_group(content, &tokens[index + 1..arrow]));
+                index = arrow + 1;
+            }
+            "match"
+                if token.kind == TokenKind::Keyword
+                    && tokens.get(index + 1).is_some_and(|t| t.is_punct("|")) =>
+            {
+                let arms = match_arm_patterns(content, tokens, index + 1);
+                if !arms.is_empty() {
+                    groups.push(ParameterGroup::Arms(arms));
+                }
+                break;
+            }
+            _ => break,
+        }
+    }
+}
+
+/// 一个参数模式；元组模式按元素拆分
+fn pattern_group(content: &str, pattern: &[Token]) -> Option<ParameterGroup> {
+    let (first, last) = (pattern.first()?, pattern.last()?);
+    let elements = tuple_elements(pattern);
+    if elements.len() < 2 {
+        return Some(ParameterGroup::Pattern(vec![normalize(
+            &content[first.span.start..last.span.end],
+        )]));
+    }
+    Some(ParameterGroup::Pattern(
+        elements
+            .into_iter()
+            .map(|element| normalize(&content[element]))
+            .collect(),
+    ))
+}
+
+/// 若模式为 `(a, b, ...)` 则返回每个元素的字节范围
+fn tuple_elements(pattern: &[Token]) -> Vec<Range<usize>> {
+    let n = pattern.len();
+    if n < 2 || !pattern[0].is_punct("(") || matching_close(pattern, 0) != Some(n - 1) {
+        return Vec::new();
+    }
+
+    let mut elements = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 1;
+    for i in 1..n {
+        let token = &pattern[i];
+        let at_end = i == n - 1;
+        if token.kind == TokenKind::Punct && !at_end {
+            match token.text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        if at_end || (depth == 0 && token.is_punct(",")) {
+            if start < i {
+                elements.push(pattern[start].span.start..pattern[i - 1].span.end);
+            }
+            start = i + 1;
+        }
+    }
+    elements
+}
+
+/// 收集 `match | p1 => e1 | p2 => e2 | panic` 中的各分支模式
+fn match_arm_patterns(content: &str, tokens: &[Token], bar: usize) -> Vec<String> {
+    let mut arms = Vec::new();
+    let mut index = bar;
+    while tokens.get(index).is_some_and(|t| t.is_punct("|")) {
+        let Some(arrow) = scan_pattern(tokens, index + 1, &["=>"]) else {
+            break;
+        };
+        if let (Some(first), Some(last)) = (tokens.get(index + 1), tokens.get(arrow - 1)) {
+            arms.push(normalize(&content[first.span.start..last.span.end]));
+        }
+        // 跳过分支体，直到同一深度的下一个 `|`
+        index = arrow + 1;
+        let mut depth = 0usize;
+        while let Some(token) = tokens.get(index) {
+            if token.kind == TokenKind::Punct {
+                match token.text {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" if depth == 0 => return arms,
+                    ")" | "]" | "}" => depth -= 1,
+                    ";" if depth == 0 => return arms,
+                    "|" if depth == 0 => break,
+                    _ => {}
+                }
+            }
+            index += 1;
+        }
+    }
+    arms
+}
+
+/// 从 `start` 开始查找同一深度下的终止符，遇到 `;` 或外层闭合括号则失败
+fn scan_pattern(tokens: &[Token], start: usize, terminators: &[&str]) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            text if depth == 0 && terminators.contains(&text) => return Some(i),
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" if depth == 0 => return None,
+            ")" | "]" | "}" => depth -= 1,
+            ";" | "=" | "=>" | "," if depth == 0 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 根据定义的签名生成签名帮助，高亮调用处正在输入的参数
+pub fn signature_help(call: &CallSite, signature: &Signature) -> Option<SignatureHelp> {
+    let mut label = format!("{}: ", signature.name);
+    let mut parameters = Vec::new();
+    for (curried, group) in signature.groups.iter().enumerate() {
+        let (prefix, items, separator, split) = match group {
+            ParameterGroup::Pattern(items) => ("constraint ", items, ", ", true),
+            ParameterGroup::Generic(items) => ("constraint ", items, ", ", true),
+            ParameterGroup::Arms(arms) => ("match | ", arms, " | ", false),
+        };
+        label.push_str(prefix);
+        let tuple = split && items.len() > 1;
+        if tuple {
+            label.push('(');
+        }
+        let group_start = label.len();
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                label.push_str(separator);
+            }
+            let start = label.len();
+            label.push_str(item);
+            if tuple {
+                parameters.push(Parameter {
+                    curried,
+                    element: Some(i),
+                    label: start..label.len(),
+                });
+            }
+        }
+        if !tuple {
+            parameters.push(Parameter {
+                curried,
+                element: None,
+                label: group_start..label.len(),
+            });
+        }
+        if tuple {
+            label.push(')');
+        }
+        label.push_str(" => ");
+    }
+    label.push_str("...");
+
+    if parameters.is_empty() {
+        return None;
+    }
+
+    let active = parameters
+        .iter()
+        .position(|p| {
+            p.curried == call.applied && p.element.is_none_or(|element| element == call.element)
+        })
+        .unwrap_or(parameters.len()) as u32;
+
+    let parameters = parameters
+        .iter()
+        .map(|p| ParameterInformation {
+            label: ParameterLabel::LabelOffsets([
+                utf16_len(&label[..p.label.start]),
+                utf16_len(&label[..p.label.end]),
+            ]),
+            documentation: None,
+        })
+        .collect();
+
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation: None,
+            parameters: Some(parameters),
+            active_parameter: Some(active),
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(active),
+    })
+}
+
+fn matching_open(tokens: &[Token], close: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for i in (0..=close).rev() {
+        match tokens[i].text {
+            ")" if tokens[i].kind == TokenKind::Punct => depth += 1,
+            "(" if tokens[i].kind == TokenKind::Punct => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn matching_close(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.text {
+            "(" if token.kind == TokenKind::Punct => depth += 1,
+            ")" if token.kind == TokenKind::Punct => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn utf16_len(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `‸` 标记光标位置
+    fn call_at(source: &str) -> Option<CallSite> {
+        let offset = source.find('‸').unwrap();
+        find_call_site(&source.replace('‸', ""), offset)
+    }
+
+    /// 按 `name` 的第一个定义生成签名帮助，返回 (签名文本, 各参数文本, 活动参数)
+    fn help(definition: &str, name: &str, call: &str) -> (String, Vec<String>, u32) {
+        let def_offset = definition.find(&format!("{name}:")).unwrap();
+        let signature = lexical_signature(definition, def_offset).unwrap();
+        let help = signature_help(&call_at(call).unwrap(), &signature).unwrap();
+        let information = &help.signatures[0];
+        let utf16: Vec<u16> = information.label.encode_utf16().collect();
+        let parameters = information
+            .parameters
+            .iter()
+            .flatten()
+            .map(|parameter| match parameter.label {
+                ParameterLabel::LabelOffsets([start, end]) => {
+                    String::from_utf16(&utf16[start as usize..end as usize]).unwrap()
+                }
+                ParameterLabel::Simple(_) => panic!("parameters are labelled by offsets"),
+            })
+            .collect();
+        (
+            information.label.clone(),
+            parameters,
+            help.active_parameter.unwrap(),
+        )
+    }
+
+    #[test]
+    fn call_site_skips_applied_argument_groups() {
+        let call = call_at("take(drop(s)(start))(‸").unwrap();
+        assert_eq!(
+            (call.callee.as_str(), call.applied, call.element),
+            ("take", 1, 0)
+        );
+
+        let call = call_at("take(drop(s)(‸").unwrap();
+        assert_eq!(
+            (call.callee.as_str(), call.applied, call.element),
+            ("drop", 1, 0)
+        );
+    }
+
+    #[test]
+    fn call_site_counts_only_top_level_commas() {
+        let call = call_at("slice(g(a, b), [1, 2], ‸").unwrap();
+        assert_eq!(
+            (call.callee.as_str(), call.applied, call.element),
+            ("slice", 0, 2)
+        );
+    }
+
+    #[test]
+    fn no_call_site_inside_comments_or_strings() {
+        assert!(call_at("f(1, // note ‸").is_none());
+        assert!(call_at("f(\"abc‸").is_none());
+        assert!(call_at("f(/* open ‸").is_none());
+        assert!(call_at("f(1); g‸").is_none());
+    }
+
+    #[test]
+    fn curried_parameters_follow_applied_groups() {
+        let definition =
+            "let constraint take: any = constraint l: any => constraint n: nat => l;\n";
+        let (label, parameters, active) = help(definition, "take", "take(xs)(‸");
+        assert_eq!(label, "take: constraint l: any => constraint n: nat => ...");
+        assert_eq!(parameters, ["l: any", "n: nat"]);
+        assert_eq!(active, 1);
+    }
+
+    #[test]
+    fn tuple_parameters_are_split_into_elements() {
+        let definition =
+            "let constraint slice: any = constraint (s: String, start: nat, end: nat) => s;\n";
+        let (label, parameters, active) = help(definition, "slice", "slice(s, 1, ‸");
+        assert_eq!(
+            label,
+            "slice: constraint (s: String, start: nat, end: nat) => ..."
+        );
+        assert_eq!(parameters, ["s: String", "start: nat", "end: nat"]);
+        assert_eq!(active, 2);
+    }
+
+    #[test]
+    fn parameter_offsets_count_utf16_code_units() {
+        // 😀 占两个 UTF-16 码元，按字节计算的偏移会落在错误的位置
+        let definition = "let constraint f: any = constraint (a: \"😀\", b: nat) => a;\n";
+        let (_, parameters, active) = help(definition, "f", "f(x, ‸");
+        assert_eq!(parameters, ["a: \"😀\"", "b: nat"]);
+        assert_eq!(active, 1);
+    }
+
+    #[test]
+    fn match_arms_form_a_single_parameter() {
+        let definition = "\
+let constraint not: any = match
+    | assert true => false
+    | assert false => true
+    | panic;
+";
+        let (label, parameters, active) = help(definition, "not", "not(‸");
+        assert_eq!(label, "not: match | assert true | assert false => ...");
+        assert_eq!(parameters, ["assert true | assert false"]);
+        assert_eq!(active, 0);
+    }
+}
@@ -0,0 +1,698 @@
+use std::cmp::Reverse;
+use std::iter::Peekable;
+use std::ops::Range as ByteRange;
+
+use mutica::mutica_compiler::parser::{
+    WithLocation,
+    ast::{FlowedMetaData, LinearTypeAst},
+};
+use mutica::mutica_core::util::source_info::SourceFile;
+use tower_lsp::lsp_types::{DocumentSymbol, Range, SymbolKind};
+
+use crate::lsp::ast_processor::child_nodes;
+use crate::lsp::hover::render_constraint;
+use crate::lsp::lexer::{Lexer, Token, TokenKind};
+use crate::lsp::utils::offset_to_position;
+
+type FlowedNode = WithLocation<LinearTypeAst, FlowedMetaData>;
+
+/// 词法扫描时声明约束最多保留的 token 数
+const MAX_CONSTRAINT_TOKENS: usize = 48;
+
+/// 大纲中的一个符号，范围均为字节偏移
+struct Candidate {
+    name: String,
+    kind: SymbolKind,
+    detail: Option<String>,
+    span: ByteRange<usize>,
+    selection: ByteRange<usize>,
+}
+
+/// 由分析后的语法树生成文档大纲
+///
+/// 符号来自当前文件中的 `Bind`、`StaticFixPoint` 和 `Namespace` 节点：
+/// - `let`/`extend` 模式中的 `Bind` 为绑定，范围取整条语句，函数参数等其他绑定不进入大纲；
+/// - `StaticFixPoint` 为 `loop`/`rec`/`dyn_rec` 不动点；
+/// - 不属于任何顶层语句的 `Namespace` 组成末尾的导出链。
+pub fn collect_document_symbols(
+    root: &FlowedNode,
+    source_file: &SourceFile,
+) -> Vec<DocumentSymbol> {
+    let content = source_file.content();
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let statements = binding_statements(&tokens);
+    let span_in_file = |node: &FlowedNode| {
+        node.location()
+            .filter(|loc| loc.source() == source_file)
+            .map(|loc| loc.span())
+    };
+
+    let mut candidates = Vec::new();
+    let mut exports = Vec::new();
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        match node.value() {
+            LinearTypeAst::Bind { var, expr } => {
+                if let Some(selection) = var
+                    .location()
+                    .filter(|loc| loc.source() == source_file)
+                    .map(|loc| loc.span())
+                    && let Some(statement) = statements
+                        .iter()
+                        .find(|s| s.pattern.contains(&selection.start))
+                {
+                    let name = &content[selection.clone()];
+                    let (kind, detail) = if statement.keyword == "extend" {
+                        (SymbolKind::OPERATOR, "extend".to_string())
+                    } else if statement.destructuring {
+                        let kind = classify_name(name).unwrap_or(SymbolKind::VARIABLE);
+                        (kind, render_constraint(expr))
+                    } else {
+                        let kind = classify_binding(name, &tokens[statement.value.clone()]);
+                        (kind, render_constraint(expr))
+                    };
+                    candidates.push(Candidate {
+                        name: name.to_string(),
+                        kind,
+                        detail: Some(detail),
+                        span: statement.span.clone(),
+                        selection,
+                    });
+                }
+            }
+            LinearTypeAst::StaticFixPoint { var, expr } => {
+                if let Some(span) = span_in_file(node)
+                    && let Some(selection) = var
+                        .location()
+                        .filter(|loc| loc.source() == source_file)
+                        .map(|loc| loc.span())
+                {
+                    let keyword = tokens
+                        .iter()
+                        .find(|t| t.span.start == span.start && t.kind == TokenKind::Keyword)
+                        .map(|t| t.text.to_string());
+                    let function_like = matches!(
+                        expr.value(),
+                        LinearTypeAst::Match { .. } | LinearTypeAst::Generic { .. }
+                    );
+                    candidates.push(Candidate {
+                        name: content[selection.clone()].to_string(),
+                        kind: if function_like || keyword.as_deref() == Some("loop") {
+                            SymbolKind::FUNCTION
+                        } else {
+                            SymbolKind::CLASS
+                        },
+                        detail: keyword,
+                        span,
+                        selection,
+                    });
+                }
+            }
+            LinearTypeAst::Namespace { tag, expr } => {
+                if let Some(span) = span_in_file(node)
+                    && !statements.iter().any(|s| s.span.contains(&span.start))
+                {
+                    let selection = tag
+                        .location()
+                        .map(|loc| loc.span())
+                        .unwrap_or(span.start..span.start + tag.value().len());
+                    exports.push(Candidate {
+                        name: tag.value().clone(),
+                        kind: SymbolKind::FIELD,
+                        detail: Some(render_constraint(expr)),
+                        span,
+                        selection,
+                    });
+                    // 导出值中的标签属于值本身，不再单独列出
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        pending.extend(child_nodes(node));
+    }
+
+    // 语法树中没有留下名字位置的 `extend` 按语句补充
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind == TokenKind::Keyword
+            && token.text == "extend"
+            && !candidates.iter().any(|c| {
+                tokens
+                    .get(i + 1)
+                    .is_some_and(|name| name.span == c.selection)
+            })
+        {
+            collect_extend(&tokens, i, &mut candidates);
+        }
+    }
+
+    exports.sort_by_key(|c| c.span.start);
+    if let (Some(first), Some(last)) = (exports.first(), exports.last()) {
+        let interface = Candidate {
+            name: "exports".to_string(),
+            kind: SymbolKind::INTERFACE,
+            detail: None,
+            span: first.span.start..last.span.end,
+            selection: first.selection.clone(),
+        };
+        candidates.push(interface);
+        candidates.append(&mut exports);
+    }
+
+    candidates.sort_by_key(|c| (c.span.start, Reverse(c.span.end)));
+    candidates.dedup_by(|a, b| a.selection == b.selection);
+    let mut iter = candidates.into_iter().peekable();
+    nest(content, &mut iter, None)
+}
+
+/// 顶层及嵌套的 `let`/`extend` 语句
+struct BindingStatement {
+    keyword: &'static str,
+    /// 绑定名所在的字节范围：单个绑定为名字本身，解构为 `{ ... }` 部分
+    pattern: ByteRange<usize>,
+    /// 整条语句的字节范围
+    span: ByteRange<usize>,
+    /// 值部分的 token 下标范围
+    value: ByteRange<usize>,
+    /// `let constraint { ... } = ...` 形式的解构
+    destructuring: bool,
+}
+
+fn binding_statements(tokens: &[Token]) -> Vec<BindingStatement> {
+    let mut statements = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Keyword {
+            continue;
+        }
+        let keyword = match token.text {
+            "let" => "let",
+            "extend" => "extend",
+            _ => continue,
+        };
+        let pattern_start = if tokens.get(i + 1).is_some_and(|t| t.text == "constraint") {
+            i + 2
+        } else {
+            i + 1
+        };
+        let separator = if keyword == "let" { "=" } else { ":" };
+        let Some(split) = find_at_depth(tokens, pattern_start, separator) else {
+            continue;
+        };
+        let destructuring = tokens[pattern_start].is_punct("{");
+        // 约束部分中的 `x: T` 同样是 Bind，单个绑定只认关键字后的名字
+        let pattern = if destructuring {
+            tokens[pattern_start].span.start..tokens[split].span.start
+        } else {
+            tokens[pattern_start].span.clone()
+        };
+        let end = statement_end(tokens, i, false);
+        statements.push(BindingStatement {
+            keyword,
+            pattern,
+            span: token.span.start..tokens[end - 1].span.end,
+            value: (split + 1).min(end)..end,
+            destructuring,
+        });
+    }
+    statements
+}
+
+/// 按词法扫描生成大纲：`let` 绑定、`loop`/`rec`/`dyn_rec` 不动点、`extend` 块以及末尾的导出链
+///
+/// 不经过完整分析，用于工作区索引中未打开的文件以及解析失败的文档；
+/// 嵌套关系由各符号的源码范围决定。
+pub fn scan_symbols(content: &str) -> Vec<DocumentSymbol> {
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let mut candidates = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Keyword {
+            continue;
+        }
+        match token.text {
+            "let" => collect_let(content, &tokens, i, &mut candidates),
+            "extend" => collect_extend(&tokens, i, &mut candidates),
+            "loop" | "rec" | "dyn_rec" => collect_fix_point(&tokens, i, &mut candidates),
+            _ => {}
+        }
+    }
+    collect_exports(content, &tokens, &mut candidates);
+
+    candidates.sort_by_key(|c| (c.span.start, Reverse(c.span.end)));
+    let mut iter = candidates.into_iter().peekable();
+    nest(content, &mut iter, None)
+}
+
+/// `let <pattern> = <value>;`：单个绑定或解构出的每个名字
+fn collect_let(content: &str, tokens: &[Token], start: usize, out: &mut Vec<Candidate>) {
+    let mut pattern_start = start + 1;
+    if tokens
+        .get(pattern_start)
+        .is_some_and(|t| t.kind == TokenKind::Keyword && t.text == "constraint")
+    {
+        pattern_start += 1;
+    }
+    let Some(eq) = find_at_depth(tokens, pattern_start, "=") else {
+        return;
+    };
+    let end = statement_end(tokens, start, false);
+    let span = tokens[start].span.start..tokens[end - 1].span.end;
+    let value = &tokens[eq + 1..end];
+
+    if is_binder(tokens, pattern_start) {
+        let name = &tokens[pattern_start];
+        out.push(Candidate {
+            name: name.text.to_string(),
+            kind: classify_binding(name.text, value),
+            detail: scanned_constraint(content, name.span.end),
+            span,
+            selection: name.span.clone(),
+        });
+        return;
+    }
+
+    // 解构绑定：`let constraint { A::(A: any) & ... } = pkg;`
+    for i in pattern_start..eq {
+        if is_binder(tokens, i) {
+            let name = &tokens[i];
+            out.push(Candidate {
+                name: name.text.to_string(),
+                kind: classify_name(name.text).unwrap_or(SymbolKind::VARIABLE),
+                detail: scanned_constraint(content, name.span.end),
+                span: span.clone(),
+                selection: name.span.clone(),
+            });
+        }
+    }
+}
+
+/// `extend $"op#add": ...;`
+fn collect_extend(tokens: &[Token], start: usize, out: &mut Vec<Candidate>) {
+    if !is_binder(tokens, start + 1) {
+        return;
+    }
+    let name = &tokens[start + 1];
+    let end = statement_end(tokens, start, false);
+    out.push(Candidate {
+        name: name.text.to_string(),
+        kind: SymbolKind::OPERATOR,
+        detail: Some("extend".to_string()),
+        span: tokens[start].span.start..tokens[end - 1].span.end,
+        selection: name.span.clone(),
+    });
+}
+
+/// `loop go: ...` 作用到所在块的末尾，`rec`/`dyn_rec` 作用到所在表达式的末尾
+fn collect_fix_point(tokens: &[Token], start: usize, out: &mut Vec<Candidate>) {
+    if !is_binder(tokens, start + 1) {
+        return;
+    }
+    let keyword = tokens[start].text;
+    let name = &tokens[start + 1];
+    let end = statement_end(tokens, start, keyword == "loop");
+    let body = &tokens[start + 3..end];
+    let kind = if keyword == "loop" || is_function_like(body) {
+        SymbolKind::FUNCTION
+    } else {
+        SymbolKind::CLASS
+    };
+    out.push(Candidate {
+        name: name.text.to_string(),
+        kind,
+        detail: Some(keyword.to_string()),
+        span: tokens[start].span.start..tokens[end - 1].span.end,
+        selection: name.span.clone(),
+    });
+}
+
+/// 文件末尾的 `Label::name & ...` 导出链，作为模块的公开接口
+fn collect_exports(content: &str, tokens: &[Token], out: &mut Vec<Candidate>) {
+    let labels = export_chain(tokens);
+    let (Some(first), Some(last)) = (labels.first(), labels.last()) else {
+        return;
+    };
+    let chain_span = first.0.span.start..last.1.end;
+    let selection = first.0.span.clone();
+
+    for (label, value) in &labels {
+        out.push(Candidate {
+            name: label.text.to_string(),
+            kind: SymbolKind::FIELD,
+            detail: Some(
+                content[value.clone()]
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            span: label.span.start..value.end,
+            selection: label.span.clone(),
+        });
+    }
+    out.push(Candidate {
+        name: "exports".to_string(),
+        kind: SymbolKind::INTERFACE,
+        detail: None,
+        span: chain_span,
+        selection,
+    });
+}
+
+/// 模块接口：导出链中的标签，按出现顺序去重
+pub fn exported_labels(content: &str) -> Vec<String> {
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    let mut labels: Vec<String> = Vec::new();
+    for (label, _) in export_chain(&tokens) {
+        if !labels.iter().any(|l| l == label.text) {
+            labels.push(label.text.to_string());
+        }
+    }
+    labels
+}
+
+/// 导出链中第一个标签的字节偏移，没有导出链时返回 None
+pub fn export_chain_start(content: &str) -> Option<usize> {
+    let tokens: Vec<Token> = Lexer::new(content).filter(|t| !t.is_comment()).collect();
+    export_chain(&tokens)
+        .first()
+        .map(|(label, _)| label.span.start)
+}
+
+/// 解析顶层最后一个表达式中的导出链，返回 (标签 token, 导出值的字节范围)
+pub fn export_chain<'a, 'b>(tokens: &'b [Token<'a>]) -> Vec<(&'b Token<'a>, ByteRange<usize>)> {
+    let mut depth = 0usize;
+    let mut chain_start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth = depth.saturating_sub(1),
+            ";" if depth == 0 => chain_start = i + 1,
+            _ => {}
+        }
+    }
+
+    let mut labels = Vec::new();
+    let mut segment_start = chain_start;
+    depth = 0;
+    for i in chain_start..=tokens.len() {
+        let at_separator = match tokens.get(i) {
+            None => true,
+            Some(token) if token.kind == TokenKind::Punct => match token.text {
+                "(" | "[" | "{" => {
+                    depth += 1;
+                    false
+                }
+                ")" | "]" | "}" => {
+                    depth = depth.saturating_sub(1);
+                    false
+                }
+                "&" => depth == 0,
+                _ => false,
+            },
+            Some(_) => false,
+        };
+        if !at_separator {
+            continue;
+        }
+        let segment = &tokens[segment_start..i];
+        if segment.len() >= 3 && segment[0].kind == TokenKind::Ident && segment[1].is_punct("::") {
+            labels.push((
+                &segment[0],
+                segment[2].span.start..segment[segment.len() - 1].span.end,
+            ));
+        }
+        segment_start = i + 1;
+    }
+    labels
+}
+
+fn nest<I>(
+    content: &str,
+    iter: &mut Peekable<I>,
+    parent: Option<&ByteRange<usize>>,
+) -> Vec<DocumentSymbol>
+where
+    I: Iterator<Item = Candidate>,
+{
+    let mut symbols = Vec::new();
+    while let Some(candidate) =
+        iter.next_if(|c| parent.is_none_or(|p| c.span.end <= p.end && c.span != *p))
+    {
+        let children = nest(content, iter, Some(&candidate.span));
+        #[allow(deprecated)]
+        symbols.push(DocumentSymbol {
+            name: candidate.name,
+            detail: candidate.detail,
+            kind: candidate.kind,
+            tags: None,
+            deprecated: None,
+            range: byte_range_to_range(content, &candidate.span),
+            selection_range: byte_range_to_range(content, &candidate.selection),
+            children: if children.is_empty() {
+                None
+            } else {
+                Some(children)
+            },
+        });
+    }
+    symbols
+}
+
+/// 根据名字和绑定的值推断符号种类
+fn classify_binding(name: &str, value: &[Token]) -> SymbolKind {
+    if let Some(kind) = classify_name(name) {
+        return kind;
+    }
+    if value
+        .first()
+        .is_some_and(|t| t.kind == TokenKind::Keyword && t.text == "import")
+    {
+        SymbolKind::MODULE
+    } else if is_function_like(value) {
+        SymbolKind::FUNCTION
+    } else {
+        SymbolKind::VARIABLE
+    }
+}
+
+/// 仅凭名字即可确定的种类：运算符扩展和（首字母大写的）类型构造器
+fn classify_name(name: &str) -> Option<SymbolKind> {
+    if name.starts_with("$\"op#") {
+        Some(SymbolKind::OPERATOR)
+    } else if name.starts_with(char::is_uppercase) {
+        Some(SymbolKind::CLASS)
+    } else {
+        None
+    }
+}
+
+/// 值是否为函数式约束：`constraint x => ...`、`match ...` 或以它们为主体的不动点
+fn is_function_like(value: &[Token]) -> bool {
+    match value.first() {
+        Some(t) if t.kind == TokenKind::Keyword => match t.text {
+            "constraint" | "match" => true,
+            "rec" | "dyn_rec" | "loop" if is_binder(value, 1) => is_function_like(&value[3..]),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// `name:`（而非 `name::`）形式的绑定
+fn is_binder(tokens: &[Token], i: usize) -> bool {
+    tokens.get(i).is_some_and(|t| t.kind == TokenKind::Ident)
+        && tokens.get(i + 1).is_some_and(|t| t.is_punct(":"))
+}
+
+/// 从 `start` 开始查找同一括号深度下的 `punct`，遇到 `;` 或闭合括号则放弃
+fn find_at_depth(tokens: &[Token], start: usize, punct: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" if depth == 0 => return None,
+            ")" | "]" | "}" => depth -= 1,
+            ";" if depth == 0 => return None,
+            text if text == punct && depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 语句结束位置（不含）：同一深度下的 `;`（包含在内）或外层闭合括号
+/// `to_block_end` 为真时忽略 `;`，一直延伸到所在块的末尾
+pub fn statement_end(tokens: &[Token], start: usize, to_block_end: bool) -> usize {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" if depth == 0 => return i.max(start + 1),
+            ")" | "]" | "}" => depth -= 1,
+            ";" if depth == 0 && !to_block_end => return i + 1,
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// 读取定义名之后 `: ...` 部分的约束表达式文本
+///
+/// 约束在深度为 0 的 `=`、`=>`、`,`、`;`、`~` 或闭合括号处结束。
+fn scanned_constraint(content: &str, name_end: usize) -> Option<String> {
+    let rest = content.get(name_end..)?;
+    let mut tokens = Lexer::new(rest).filter(|t| !t.is_comment());
+    if !tokens.next()?.is_punct(":") {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut first = None;
+    let mut last = None;
+    let mut truncated = false;
+    for (count, token) in tokens.enumerate() {
+        if count >= MAX_CONSTRAINT_TOKENS {
+            truncated = true;
+            break;
+        }
+        if token.kind == TokenKind::Punct {
+            match token.text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" if depth == 0 => break,
+                ")" | "]" | "}" => depth -= 1,
+                "=" | "=>" | "," | ";" | "~" if depth == 0 => break,
+                _ => {}
+            }
+        }
+        first.get_or_insert(token.span.start);
+        last = Some(token.span.end);
+    }
+
+    let text = rest.get(first?..last?)?;
+    let mut constraint = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if truncated {
+        constraint.push_str(" …");
+    }
+    Some(constraint)
+}
+
+fn byte_range_to_range(content: &str, span: &ByteRange<usize>) -> Range {
+    Range {
+        start: offset_to_position(content, span.start),
+        end: offset_to_position(content, span.end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按先序列出符号，子符号写作 `父/子`
+    fn outline(symbols: &[DocumentSymbol]) -> Vec<(String, SymbolKind)> {
+        fn visit(symbols: &[DocumentSymbol], prefix: &str, out: &mut Vec<(String, SymbolKind)>) {
+            for symbol in symbols {
+                let path = format!("{prefix}{}", symbol.name);
+                out.push((path.clone(), symbol.kind));
+                visit(
+                    symbol.children.as_deref().unwrap_or_default(),
+                    &format!("{path}/"),
+                    out,
+                );
+            }
+        }
+        let mut out = Vec::new();
+        visit(symbols, "", &mut out);
+        out
+    }
+
+    fn entry(path: &str, kind: SymbolKind) -> (String, SymbolKind) {
+        (path.to_string(), kind)
+    }
+
+    #[test]
+    fn scan_finds_let_loop_rec_and_extend_bindings() {
+        let content = r#"let constraint id: any = constraint x: any => x;
+let constraint count: nat = 3;
+extend $"op#add": match
+    | constraint (a: nat, b: nat) => a
+    | panic;
+let constraint sum: any = constraint n: nat => {
+    loop go: constraint (acc: nat, i: nat) = (0, 0);
+    if i == n then acc else go((acc + i, i + 1))
+};
+let constraint tree: any = rec Tree: Leaf | Node::(Tree, Tree);
+"#;
+        let symbols = scan_symbols(content);
+        assert_eq!(
+            outline(&symbols),
+            [
+                entry("id", SymbolKind::FUNCTION),
+                entry("count", SymbolKind::VARIABLE),
+                entry("$\"op#add\"", SymbolKind::OPERATOR),
+                entry("sum", SymbolKind::FUNCTION),
+                entry("sum/go", SymbolKind::FUNCTION),
+                entry("tree", SymbolKind::VARIABLE),
+                entry("tree/Tree", SymbolKind::CLASS),
+            ]
+        );
+        assert_eq!(symbols[1].detail.as_deref(), Some("nat"));
+        assert_eq!(symbols[2].detail.as_deref(), Some("extend"));
+        // `loop` 作用到所在块的末尾
+        let go = &symbols[3].children.as_ref().unwrap()[0];
+        assert_eq!(go.detail.as_deref(), Some("loop"));
+        assert_eq!(go.range.end.line, 7);
+    }
+
+    #[test]
+    fn export_chain_lists_labels_in_order() {
+        let content = "\
+let constraint a: any = 1;
+let constraint f: any = constraint x: any => x;
+A::a &
+f::f &
+A::(a)";
+        assert_eq!(exported_labels(content), ["A", "f"]);
+        assert_eq!(export_chain_start(content), content.find("A::a"));
+        assert_eq!(
+            outline(&scan_symbols(content)),
+            [
+                entry("a", SymbolKind::VARIABLE),
+                entry("f", SymbolKind::FUNCTION),
+                entry("exports", SymbolKind::INTERFACE),
+                entry("exports/A", SymbolKind::FIELD),
+                entry("exports/f", SymbolKind::FIELD),
+                entry("exports/A", SymbolKind::FIELD),
+            ]
+        );
+
+        // 最后一条语句之后没有导出链
+        assert!(exported_labels("let constraint a: any = 1;").is_empty());
+        assert_eq!(export_chain_start("let constraint a: any = 1;"), None);
+    }
+
+    #[test]
+    fn scan_survives_documents_that_do_not_parse() {
+        // 缺少值、括号未闭合
+        let content = "\
+let constraint first: nat = ;
+let constraint second: any = constraint x: any => {
+    let constraint inner: nat = 1;
+";
+        assert_eq!(
+            outline(&scan_symbols(content)),
+            [
+                entry("first", SymbolKind::VARIABLE),
+                entry("second", SymbolKind::FUNCTION),
+                entry("second/inner", SymbolKind::VARIABLE),
+            ]
+        );
+    }
+}
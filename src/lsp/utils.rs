@@ -33,23 +33,27 @@ where
     strip_ansi(&out)
 }
 
-/// 将字节偏移转换为行列号
+/// 将字节偏移转换为行列号，列号以 UTF-16 码元计
+///
+/// 与文档的 rope 一致，`\n`、`\r\n` 和单独的 `\r` 都是换行。
 pub fn offset_to_position(content: &str, offset: usize) -> Position {
     let mut line = 0u32;
     let mut col = 0u32;
-    let mut current_offset = 0;
+    let mut chars = content.char_indices().peekable();
 
-    for ch in content.chars() {
+    while let Some((current_offset, ch)) = chars.next() {
         if current_offset >= offset {
             break;
         }
-        if ch == '\n' {
-            line += 1;
-            col = 0;
-        } else {
-            col += 1;
+        match ch {
+            // `\r\n` 在 `\n` 处换行
+            '\r' if chars.peek().is_some_and(|&(_, next)| next == '\n') => {}
+            '\n' | '\r' => {
+                line += 1;
+                col = 0;
+            }
+            _ => col += ch.len_utf16() as u32,
         }
-        current_offset += ch.len_utf8();
     }
 
     Position {
@@ -58,6 +62,38 @@ pub fn offset_to_position(content: &str, offset: usize) -> Position {
     }
 }
 
+/// 将 Position 转换为字节偏移，列号以 UTF-16 码元计，换行规则与 `offset_to_position` 相同
+pub fn position_to_offset(content: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        let rest = &content[line_start..];
+        let line_end = rest.find(['\n', '\r'])?;
+        let break_len = if rest[line_end..].starts_with("\r\n") {
+            2
+        } else {
+            1
+        };
+        line_start += line_end + break_len;
+    }
+
+    // 找到目标行，逐字符累加 UTF-16 码元直到达到 desired，超出则为行尾
+    let rest = &content[line_start..];
+    let line = &rest[..rest.find(['\n', '\r']).unwrap_or(rest.len())];
+    let desired = position.character as usize;
+    let mut units = 0;
+    let col_offset = line
+        .char_indices()
+        .find(|(_, ch)| {
+            let reached = units >= desired;
+            units += ch.len_utf16();
+            reached
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(line.len());
+
+    Some(line_start + col_offset)
+}
+
 /// 辅助函数：判断位置是否在范围内
 pub fn position_in_range(pos: &Position, range: &Range) -> bool {
     if pos.line < range.start.line || pos.line > range.end.line {
@@ -79,3 +115,40 @@ pub fn ranges_equal(a: &Range, b: &Range) -> bool {
         && a.end.line == b.end.line
         && a.end.character == b.end.character
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_count_utf16_code_units() {
+        // 😀 占四个字节、两个 UTF-16 码元
+        let content = "let 😀 = f(x);\nlet y = 1;";
+        let x = content.find('x').unwrap();
+        assert_eq!(position_to_offset(content, Position::new(0, 11)), Some(x));
+        assert_eq!(offset_to_position(content, x), Position::new(0, 11));
+        // 超出行尾的列号停在行尾
+        let newline = content.find('\n').unwrap();
+        assert_eq!(
+            position_to_offset(content, Position::new(0, 40)),
+            Some(newline)
+        );
+        assert_eq!(
+            position_to_offset(content, Position::new(1, 4)),
+            Some(newline + 5)
+        );
+    }
+
+    #[test]
+    fn lone_carriage_returns_and_crlf_break_lines() {
+        let content = "a\rbc\r\nd\ne";
+        assert_eq!(offset_to_position(content, 3), Position::new(1, 1));
+        assert_eq!(position_to_offset(content, Position::new(1, 1)), Some(3));
+        // `\r\n` 中间的偏移仍在行尾
+        assert_eq!(offset_to_position(content, 5), Position::new(1, 2));
+        assert_eq!(position_to_offset(content, Position::new(1, 9)), Some(4));
+        assert_eq!(position_to_offset(content, Position::new(2, 0)), Some(6));
+        assert_eq!(position_to_offset(content, Position::new(3, 0)), Some(8));
+        assert_eq!(position_to_offset(content, Position::new(4, 0)), None);
+    }
+}
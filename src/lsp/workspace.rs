@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{DocumentSymbol, Location, SymbolInformation, SymbolKind, Url};
+
+use crate::lsp::symbols::{exported_labels, scan_symbols};
+
+/// 工作区符号搜索最多返回的条目数
+const MAX_WORKSPACE_SYMBOLS: usize = 256;
+
+/// 扫描工作区时跳过的目录
+pub const IGNORED_DIRS: &[&str] = &["target", "node_modules", "out"];
+
+/// 递归收集工作区目录下的所有 `.mu` 文件
+pub fn find_mu_files(folders: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending: Vec<PathBuf> = folders.to_vec();
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !name.starts_with('.') && !IGNORED_DIRS.contains(&name.as_ref()) {
+                    pending.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "mu") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+/// 工作区中所有 `.mu` 文件的符号索引
+///
+/// 在 `initialized` 时扫描一次工作区，之后随文档修改、保存和文件监视事件逐个文件更新，
+/// 工作区符号搜索和自动导入都只查询索引，不再在请求时扫描磁盘。
+#[derive(Debug, Default)]
+pub struct WorkspaceIndex {
+    files: HashMap<PathBuf, IndexedFile>,
+}
+
+#[derive(Debug)]
+struct IndexedFile {
+    /// 可被搜索到的符号，已带上所属容器名
+    symbols: Vec<SymbolInformation>,
+    /// 导出链中的标签
+    exports: Vec<String>,
+}
+
+impl WorkspaceIndex {
+    /// 扫描工作区目录下的所有 `.mu` 文件建立索引
+    pub fn build(folders: &[PathBuf]) -> Self {
+        let mut index = Self::default();
+        index.add_folders(folders);
+        index
+    }
+
+    /// 将新加入的工作区目录下的文件加入索引
+    pub fn add_folders(&mut self, folders: &[PathBuf]) {
+        for path in find_mu_files(folders) {
+            self.reload(&path);
+        }
+    }
+
+    /// 合并另一份索引，同一文件以 `other` 为准
+    pub fn merge(&mut self, other: Self) {
+        self.files.extend(other.files);
+    }
+
+    /// 移除某个工作区目录下的所有文件
+    pub fn remove_folder(&mut self, folder: &Path) {
+        self.files.retain(|path, _| !path.starts_with(folder));
+    }
+
+    /// 用给定内容（通常是编辑器中的版本）更新一个文件的索引
+    pub fn update(&mut self, path: &Path, content: &str) {
+        let Ok(uri) = Url::from_file_path(path) else {
+            return;
+        };
+        let module = module_name(path);
+        let symbols = indexable_symbols(&scan_symbols(content), &module)
+            .into_iter()
+            .map(|(symbol, container)| to_symbol_information(symbol, container, &uri))
+            .collect();
+        self.files.insert(
+            path.to_path_buf(),
+            IndexedFile {
+                symbols,
+                exports: exported_labels(content),
+            },
+        );
+    }
+
+    /// 从磁盘重新读取一个文件，文件不存在时将其移出索引
+    pub fn reload(&mut self, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(content) => self.update(path, &content),
+            Err(_) => self.remove(path),
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// 模糊搜索顶层符号和导出标签
+    pub fn symbols(&self, query: &str) -> Vec<SymbolInformation> {
+        let mut matches: Vec<(i64, &SymbolInformation)> = self
+            .files
+            .values()
+            .flat_map(|file| &file.symbols)
+            .filter_map(|symbol| Some((fuzzy_score(query, &symbol.name)?, symbol)))
+            .collect();
+
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+        matches
+            .into_iter()
+            .take(MAX_WORKSPACE_SYMBOLS)
+            .map(|(_, info)| info.clone())
+            .collect()
+    }
+
+    /// 导出链提供了 `label` 的模块，按路径排序
+    pub fn modules_exporting(&self, label: &str) -> Vec<PathBuf> {
+        let mut modules: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, file)| file.exports.iter().any(|export| export == label))
+            .map(|(path, _)| path.clone())
+            .collect();
+        modules.sort();
+        modules
+    }
+}
+
+/// 可被工作区搜索到的符号：顶层绑定、`extend` 块以及导出链中的标签
+/// 返回 (符号, 所属容器名)
+fn indexable_symbols<'a>(
+    symbols: &'a [DocumentSymbol],
+    module: &str,
+) -> Vec<(&'a DocumentSymbol, String)> {
+    let mut result = Vec::new();
+    for symbol in symbols {
+        if symbol.kind == SymbolKind::INTERFACE {
+            for label in symbol.children.iter().flatten() {
+                result.push((label, format!("{} exports", module)));
+            }
+        } else {
+            result.push((symbol, module.to_string()));
+        }
+    }
+    result
+}
+
+#[allow(deprecated)]
+fn to_symbol_information(
+    symbol: &DocumentSymbol,
+    container: String,
+    uri: &Url,
+) -> SymbolInformation {
+    SymbolInformation {
+        name: symbol.name.clone(),
+        kind: symbol.kind,
+        tags: None,
+        deprecated: None,
+        location: Location {
+            uri: uri.clone(),
+            range: symbol.selection_range,
+        },
+        container_name: Some(container),
+    }
+}
+
+fn module_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+/// 模糊匹配：`query` 的字符需按顺序（忽略大小写）出现在 `name` 中
+///
+/// 连续匹配和单词开头（名字开头或 `_` 之后）的匹配得分更高，不匹配时返回 None。
+pub fn fuzzy_score(query: &str, name: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let name_chars: Vec<char> = name.chars().collect();
+    let mut score = 0i64;
+    let mut name_index = 0;
+    let mut previous_match: Option<usize> = None;
+
+    for q in query.chars() {
+        let q = q.to_ascii_lowercase();
+        let found =
+            (name_index..name_chars.len()).find(|&i| name_chars[i].to_ascii_lowercase() == q)?;
+
+        score += 1;
+        if previous_match.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !name_chars[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        previous_match = Some(found);
+        name_index = found + 1;
+    }
+
+    // 越短的名字越接近完整匹配
+    Some(score * 16 - name_chars.len() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_ranks_above_scattered_subsequence() {
+        let prefix = fuzzy_score("map", "map_list").unwrap();
+        let scattered = fuzzy_score("map", "make_ap").unwrap();
+        assert!(prefix > scattered);
+        // 单词开头的匹配优先于单词中间的匹配
+        assert!(fuzzy_score("len", "length").unwrap() > fuzzy_score("len", "silent").unwrap());
+        // 同样的匹配下较短的名字优先
+        assert!(fuzzy_score("map", "map").unwrap() > prefix);
+    }
+
+    #[test]
+    fn matching_ignores_case() {
+        assert_eq!(
+            fuzzy_score("MAP", "map_list"),
+            fuzzy_score("map", "map_list")
+        );
+        assert_eq!(
+            fuzzy_score("nts", "nat_to_string"),
+            fuzzy_score("NTS", "nat_to_String")
+        );
+        assert!(fuzzy_score("rbt", "RBTree").is_some());
+    }
+
+    #[test]
+    fn characters_must_appear_in_order() {
+        assert_eq!(fuzzy_score("pam", "map_list"), None);
+        assert_eq!(fuzzy_score("mapx", "map_list"), None);
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+    }
+}
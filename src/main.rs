@@ -1,21 +1,48 @@
+mod cli;
 mod lsp;
 
-use lsp::Backend;
+use lsp::module_cache::ModuleCache;
+use lsp::scheduler::AnalysisScheduler;
+use lsp::workspace::WorkspaceIndex;
+use lsp::{Backend, BackendState};
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, RwLock};
 use tower_lsp::{LspService, Server};
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "fmt") {
+        std::process::exit(cli::fmt_command(&args[1..]));
+    }
+
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        documents: RwLock::new(HashMap::new()),
-        last_tokens: RwLock::new(HashMap::new()),
-        reference_table: RwLock::new(HashMap::new()),
-        variable_maps: RwLock::new(HashMap::new()),
+    let (service, socket) = LspService::new(|client| {
+        Backend::new(BackendState {
+            client,
+            documents: RwLock::new(HashMap::new()),
+            last_tokens: RwLock::new(HashMap::new()),
+            sent_tokens: RwLock::new(HashMap::new()),
+            next_result_id: AtomicU64::new(0),
+            reference_table: RwLock::new(HashMap::new()),
+            variable_maps: RwLock::new(HashMap::new()),
+            hover_tables: RwLock::new(HashMap::new()),
+            workspace_folders: RwLock::new(Vec::new()),
+            workspace_index: RwLock::new(WorkspaceIndex::default()),
+            analysis_versions: RwLock::new(HashMap::new()),
+            scheduler: AnalysisScheduler::default(),
+            module_cache: Arc::new(Mutex::new(ModuleCache::default())),
+            imported_diagnostics: RwLock::new(HashMap::new()),
+            diagnostics: RwLock::new(HashMap::new()),
+            match_spans: RwLock::new(HashMap::new()),
+            node_spans: RwLock::new(HashMap::new()),
+            document_symbols: RwLock::new(HashMap::new()),
+            signatures: RwLock::new(HashMap::new()),
+            snippet_support: RwLock::new(false),
+        })
     });
     Server::new(stdin, stdout, socket).serve(service).await;
 }